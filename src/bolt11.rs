@@ -0,0 +1,260 @@
+use crate::Result;
+use serde_json::{json, Value};
+
+const CHARSET: &str = "qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+/// Signature plus recovery id, 65 bytes in 5-bit groups
+const SIGNATURE_LEN: usize = 104;
+const URI_SCHEME: &str = "lightning:";
+/// Used when the invoice has no x field
+const DEFAULT_EXPIRY: u64 = 3600;
+
+/// Fields of a BOLT11 payment request. The signature isn't verified, this is
+/// for showing what an invoice asks for, not for deciding whether to pay it.
+pub struct Invoice {
+    pub network: String,
+    pub amount_msat: Option<u64>,
+    pub timestamp: u64,
+    pub expiry: u64,
+    pub payment_hash: String,
+    pub description: Option<String>,
+    pub description_hash: Option<String>,
+    pub payee: Option<String>,
+    pub min_final_cltv_expiry: Option<u64>,
+}
+
+impl Invoice {
+    pub fn expires_at(&self) -> u64 {
+        self.timestamp.saturating_add(self.expiry)
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "network": self.network,
+            "amount_msat": self.amount_msat,
+            "amount_sats": self.amount_msat.map(|it| it as f64 / 1000.0),
+            "timestamp": self.timestamp,
+            "expiry": self.expiry,
+            "expires_at": self.expires_at(),
+            "payment_hash": self.payment_hash,
+            "description": self.description,
+            "description_hash": self.description_hash,
+            "payee": self.payee,
+            "min_final_cltv_expiry": self.min_final_cltv_expiry,
+        })
+    }
+}
+
+/// Whether a string looks like a BOLT11 invoice, without decoding it.
+pub fn is_invoice(s: &str) -> bool {
+    let s = s.trim().to_lowercase();
+    let s = s.strip_prefix(URI_SCHEME).unwrap_or(&s);
+    s.starts_with("ln") && s.rfind('1').is_some_and(|it| it > 2)
+}
+
+pub fn decode(invoice: &str) -> Result<Invoice> {
+    let invoice = invoice.trim();
+    // Wallet URIs often keep the scheme lowercase in front of an uppercase invoice
+    let invoice = match invoice.get(..URI_SCHEME.len()) {
+        Some(scheme) if scheme.eq_ignore_ascii_case(URI_SCHEME) => &invoice[URI_SCHEME.len()..],
+        _ => invoice,
+    };
+    if invoice.chars().any(|it| it.is_lowercase()) && invoice.chars().any(|it| it.is_uppercase()) {
+        Err("invoice mixes upper and lower case")?;
+    }
+    let invoice = invoice.to_lowercase();
+    let (hrp, data) = invoice
+        .rsplit_once('1')
+        .ok_or("invoice has no bech32 separator")?;
+    let data = data
+        .chars()
+        .map(|c| CHARSET.find(c).map(|it| it as u8))
+        .collect::<Option<Vec<u8>>>()
+        .ok_or("invoice contains characters outside the bech32 alphabet")?;
+    if data.len() < 7 + SIGNATURE_LEN + 6 {
+        Err("invoice is too short")?;
+    }
+    if polymod(&[hrp_expand(hrp), data.clone()].concat()) != 1 {
+        Err("invoice checksum is invalid")?;
+    }
+    let (network, amount_msat) = parse_hrp(hrp)?;
+    let data = &data[..data.len() - 6 - SIGNATURE_LEN];
+    let mut res = Invoice {
+        network,
+        amount_msat,
+        timestamp: to_int(&data[..7]),
+        expiry: DEFAULT_EXPIRY,
+        payment_hash: "".into(),
+        description: None,
+        description_hash: None,
+        payee: None,
+        min_final_cltv_expiry: None,
+    };
+    let mut rest = &data[7..];
+    while !rest.is_empty() {
+        if rest.len() < 3 {
+            Err("invoice has a truncated tagged field")?;
+        }
+        let len = to_int(&rest[1..3]) as usize;
+        let field = rest
+            .get(3..3 + len)
+            .ok_or("invoice has a truncated tagged field")?;
+        // Fields with an unexpected length must be skipped, see BOLT11
+        match (rest[0], len) {
+            (1, 52) => res.payment_hash = hex(&to_bytes(field)),
+            (13, _) => res.description = Some(String::from_utf8(to_bytes(field))?),
+            (23, 52) => res.description_hash = Some(hex(&to_bytes(field))),
+            (19, 53) => res.payee = Some(hex(&to_bytes(field))),
+            (6 | 24, _) if len > 12 => Err("invoice has an oversized integer field")?,
+            (6, _) => res.expiry = to_int(field),
+            (24, _) => res.min_final_cltv_expiry = Some(to_int(field)),
+            _ => {}
+        }
+        rest = &rest[3 + len..];
+    }
+    if res.payment_hash.is_empty() {
+        Err("invoice has no payment hash")?;
+    }
+    Ok(res)
+}
+
+/// Splits ln + currency + optional amount with multiplier.
+fn parse_hrp(hrp: &str) -> Result<(String, Option<u64>)> {
+    let rest = hrp
+        .strip_prefix("ln")
+        .ok_or(format!("{hrp} is not a lightning invoice prefix"))?;
+    let (network, amount) = [
+        ("bcrt", "regtest"),
+        ("bc", "bitcoin"),
+        ("tbs", "signet"),
+        ("tb", "testnet"),
+    ]
+    .iter()
+    .find_map(|(prefix, network)| rest.strip_prefix(prefix).map(|it| (*network, it)))
+    .ok_or(format!("unknown currency in invoice prefix {hrp}"))?;
+    if amount.is_empty() {
+        return Ok((network.into(), None));
+    }
+    let (digits, multiplier) = match amount.chars().last() {
+        Some(c @ ('m' | 'u' | 'n' | 'p')) => (&amount[..amount.len() - 1], Some(c)),
+        _ => (amount, None),
+    };
+    let value: u64 = digits
+        .parse()
+        .map_err(|_| format!("invalid amount {amount} in invoice prefix"))?;
+    let amount_msat = match multiplier {
+        None => value.checked_mul(100_000_000_000),
+        Some('m') => value.checked_mul(100_000_000),
+        Some('u') => value.checked_mul(100_000),
+        Some('n') => value.checked_mul(100),
+        _ if !value.is_multiple_of(10) => Err("pico-bitcoin amount must be a multiple of 10")?,
+        _ => Some(value / 10),
+    }
+    .ok_or("invoice amount is too large")?;
+    Ok((network.into(), Some(amount_msat)))
+}
+
+fn polymod(values: &[u8]) -> u32 {
+    const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+    let mut chk: u32 = 1;
+    for value in values {
+        let top = chk >> 25;
+        chk = ((chk & 0x1ffffff) << 5) ^ *value as u32;
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                chk ^= generator;
+            }
+        }
+    }
+    chk
+}
+
+fn hrp_expand(hrp: &str) -> Vec<u8> {
+    let mut res: Vec<u8> = hrp.bytes().map(|it| it >> 5).collect();
+    res.push(0);
+    res.extend(hrp.bytes().map(|it| it & 31));
+    res
+}
+
+/// Big-endian integer from 5-bit groups.
+fn to_int(groups: &[u8]) -> u64 {
+    groups.iter().fold(0, |acc, it| (acc << 5) | *it as u64)
+}
+
+/// Regroups 5-bit values into bytes, dropping the zero padding at the end.
+fn to_bytes(groups: &[u8]) -> Vec<u8> {
+    let mut res = vec![];
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+    for group in groups {
+        acc = (acc << 5) | *group as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            res.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+    res
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|it| format!("{it:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Examples from BOLT11
+    const DONATION: &str = "lnbc1pvjluezsp5zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zygspp5qqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqypqdpl2pkx2ctnv5sxxmmwwd5kgetjypeh2ursdae8g6twvus8g6rfwvs8qun0dfjkxaq9qrsgq357wnc5r2ueh7ck6q93dj32dlqnls087fxdwk8qakdyafkq3yap9us6v52vjjsrvywa6rt52cm9r9zqt8r2t7mlcwspyetp5h2tztugp9lfyql";
+    const COFFEE: &str = "lnbc2500u1pvjluezsp5zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zygspp5qqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqypqdq5xysxxatsyp3k7enxv4jsxqzpu9qrsgquk0rl77nj30yxdy8j9vdx85fkpmdla2087ne0xh8nhedh8w27kyke0lp53ut353s06fv3qfegext0eh0ymjpf39tuven09sam30g4vgpfna3rh";
+    const PAYMENT_HASH: &str = "0001020304050607080900010203040506070809000102030405060708090102";
+
+    #[test]
+    fn decodes_invoice_without_amount() {
+        let invoice = decode(DONATION).unwrap();
+        assert_eq!(invoice.network, "bitcoin");
+        assert_eq!(invoice.amount_msat, None);
+        assert_eq!(invoice.timestamp, 1496314658);
+        assert_eq!(invoice.expiry, DEFAULT_EXPIRY);
+        assert_eq!(invoice.payment_hash, PAYMENT_HASH);
+        assert_eq!(
+            invoice.description.as_deref(),
+            Some("Please consider supporting this project")
+        );
+    }
+
+    #[test]
+    fn decodes_invoice_with_amount_and_expiry() {
+        let invoice = decode(COFFEE).unwrap();
+        assert_eq!(invoice.amount_msat, Some(250_000_000));
+        assert_eq!(invoice.expiry, 60);
+        assert_eq!(invoice.expires_at(), 1496314658 + 60);
+        assert_eq!(invoice.payment_hash, PAYMENT_HASH);
+        assert_eq!(invoice.description.as_deref(), Some("1 cup coffee"));
+    }
+
+    #[test]
+    fn accepts_upper_case_and_uri_prefix() {
+        assert!(decode(&COFFEE.to_uppercase()).is_ok());
+        assert!(decode(&format!("lightning:{COFFEE}")).is_ok());
+        assert!(decode(&format!("lightning:{}", COFFEE.to_uppercase())).is_ok());
+        assert!(is_invoice(&format!("LIGHTNING:{}", COFFEE.to_uppercase())));
+        assert!(is_invoice(&format!("lightning:{}", COFFEE.to_uppercase())));
+        assert!(!is_invoice("bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu"));
+    }
+
+    #[test]
+    fn rejects_mixed_case() {
+        let mixed = format!("LNBC{}", &COFFEE[4..]);
+        assert!(decode(&mixed).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_checksum() {
+        let mut bad = COFFEE.to_string();
+        let last = bad.pop().unwrap();
+        bad.push(if last == 'q' { 'p' } else { 'q' });
+        assert!(decode(&bad).is_err());
+    }
+}
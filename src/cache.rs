@@ -0,0 +1,215 @@
+use crate::{rpc, settings, Result};
+use rusqlite::{params, Connection};
+use serde_json::Value;
+
+/// Stores a full area row, geometry included, for offline lookups. Rows are
+/// kept per server so prod and staging areas don't mix. Skipped under
+/// --dry-run.
+pub fn put_area(area: &Value) -> Result<()> {
+    if rpc::is_dry_run() {
+        return Ok(());
+    }
+    let id = area["id"].as_i64().ok_or("area has no numeric id")?;
+    connect()?.execute(
+        "INSERT OR REPLACE INTO area_cache (server, id, json, cached_at) VALUES (?1, ?2, ?3, strftime('%Y-%m-%dT%H:%M:%SZ'));",
+        params![settings::api_url()?.value, id, serde_json::to_string(area)?],
+    )?;
+    Ok(())
+}
+
+pub fn areas() -> Result<Vec<Value>> {
+    let conn = connect()?;
+    let mut stmt = conn.prepare("SELECT json FROM area_cache WHERE server = ?1 ORDER BY id;")?;
+    let rows = stmt
+        .query_map(params![settings::api_url()?.value], |row| {
+            row.get::<_, String>(0)
+        })?
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    Ok(rows
+        .iter()
+        .map(|it| serde_json::from_str(it))
+        .collect::<std::result::Result<Vec<_>, _>>()?)
+}
+
+/// Alias and name of every cached area of the current server, selected in
+/// SQL so completion doesn't parse geometries. Empty when there is no
+/// database yet.
+pub fn area_aliases() -> Result<Vec<(String, Option<String>)>> {
+    let Some(conn) = settings::connect_existing()? else {
+        return Ok(vec![]);
+    };
+    let mut stmt = conn.prepare(
+        "SELECT json_extract(json, '$.tags.url_alias'), json_extract(json, '$.tags.name') FROM area_cache WHERE server = ?1 AND json_extract(json, '$.tags.url_alias') IS NOT NULL ORDER BY id;",
+    )?;
+    let rows = stmt
+        .query_map(params![settings::api_url()?.value], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    Ok(rows)
+}
+
+/// Stores an invoice as returned by get_invoice, for offline reports.
+/// Skipped under --dry-run.
+pub fn put_invoice(invoice: &Value) -> Result<()> {
+    if rpc::is_dry_run() {
+        return Ok(());
+    }
+    let id = invoice["id"].as_i64().ok_or("invoice has no numeric id")?;
+    connect()?.execute(
+        "INSERT OR REPLACE INTO invoice_cache (server, id, json, cached_at) VALUES (?1, ?2, ?3, strftime('%Y-%m-%dT%H:%M:%SZ'));",
+        params![settings::api_url()?.value, id, serde_json::to_string(invoice)?],
+    )?;
+    Ok(())
+}
+
+/// Cached invoices of the current server, by id.
+pub fn invoices() -> Result<Vec<Value>> {
+    let conn = connect()?;
+    let mut stmt = conn.prepare("SELECT json FROM invoice_cache WHERE server = ?1 ORDER BY id;")?;
+    let rows = stmt
+        .query_map(params![settings::api_url()?.value], |row| {
+            row.get::<_, String>(0)
+        })?
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    Ok(rows
+        .iter()
+        .map(|it| serde_json::from_str(it))
+        .collect::<std::result::Result<Vec<_>, _>>()?)
+}
+
+fn connect() -> Result<Connection> {
+    let conn = settings::connect()?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS area_cache (server TEXT NOT NULL, id INTEGER NOT NULL, json TEXT NOT NULL, cached_at TEXT NOT NULL, PRIMARY KEY (server, id));",
+        (),
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS area_snapshot (id INTEGER PRIMARY KEY, server TEXT NOT NULL, area_id INTEGER NOT NULL, json TEXT NOT NULL, taken_at TEXT NOT NULL);",
+        (),
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS invoice_cache (server TEXT NOT NULL, id INTEGER NOT NULL, json TEXT NOT NULL, cached_at TEXT NOT NULL, PRIMARY KEY (server, id));",
+        (),
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS wallet_snapshot (id INTEGER PRIMARY KEY, server TEXT NOT NULL, wallets INTEGER NOT NULL, confirmed_sats INTEGER NOT NULL, unconfirmed_sats INTEGER NOT NULL, taken_at TEXT NOT NULL);",
+        (),
+    )?;
+    Ok(conn)
+}
+
+pub struct Snapshot {
+    pub id: i64,
+    pub taken_at: String,
+    pub area: Value,
+}
+
+/// Saves the current state of an area so later changes can be diffed against it.
+pub fn put_area_snapshot(area: &Value) -> Result<Snapshot> {
+    let area_id = area["id"].as_i64().ok_or("area has no numeric id")?;
+    let conn = connect()?;
+    let taken_at: String = conn.query_row(
+        "INSERT INTO area_snapshot (server, area_id, json, taken_at) VALUES (?1, ?2, ?3, strftime('%Y-%m-%dT%H:%M:%SZ')) RETURNING taken_at;",
+        params![settings::api_url()?.value, area_id, serde_json::to_string(area)?],
+        |row| row.get(0),
+    )?;
+    Ok(Snapshot {
+        id: conn.last_insert_rowid(),
+        taken_at,
+        area: area.clone(),
+    })
+}
+
+/// Snapshots of an area, oldest first.
+pub fn area_snapshots(area_id: i64) -> Result<Vec<Snapshot>> {
+    let conn = connect()?;
+    let mut stmt = conn.prepare(
+        "SELECT id, taken_at, json FROM area_snapshot WHERE server = ?1 AND area_id = ?2 ORDER BY id;",
+    )?;
+    let rows = stmt
+        .query_map(params![settings::api_url()?.value, area_id], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get::<_, String>(2)?))
+        })?
+        .collect::<std::result::Result<Vec<(i64, String, String)>, _>>()?;
+    let mut res = vec![];
+    for (id, taken_at, json) in rows {
+        res.push(Snapshot {
+            id,
+            taken_at,
+            area: serde_json::from_str(&json)?,
+        });
+    }
+    Ok(res)
+}
+
+pub struct WalletSnapshot {
+    pub id: i64,
+    pub taken_at: String,
+    pub wallets: i64,
+    pub confirmed_sats: i64,
+    pub unconfirmed_sats: i64,
+}
+
+/// Saves wallet balance totals, one row per wallet summary run.
+pub fn put_wallet_snapshot(
+    wallets: i64,
+    confirmed_sats: i64,
+    unconfirmed_sats: i64,
+) -> Result<WalletSnapshot> {
+    let conn = connect()?;
+    let taken_at: String = conn.query_row(
+        "INSERT INTO wallet_snapshot (server, wallets, confirmed_sats, unconfirmed_sats, taken_at) VALUES (?1, ?2, ?3, ?4, strftime('%Y-%m-%dT%H:%M:%SZ')) RETURNING taken_at;",
+        params![settings::api_url()?.value, wallets, confirmed_sats, unconfirmed_sats],
+        |row| row.get(0),
+    )?;
+    Ok(WalletSnapshot {
+        id: conn.last_insert_rowid(),
+        taken_at,
+        wallets,
+        confirmed_sats,
+        unconfirmed_sats,
+    })
+}
+
+/// Wallet balance snapshots of the current server, oldest first.
+pub fn wallet_snapshots() -> Result<Vec<WalletSnapshot>> {
+    let conn = connect()?;
+    let mut stmt = conn.prepare(
+        "SELECT id, taken_at, wallets, confirmed_sats, unconfirmed_sats FROM wallet_snapshot WHERE server = ?1 ORDER BY id;",
+    )?;
+    let rows = stmt
+        .query_map(params![settings::api_url()?.value], |row| {
+            Ok(WalletSnapshot {
+                id: row.get(0)?,
+                taken_at: row.get(1)?,
+                wallets: row.get(2)?,
+                confirmed_sats: row.get(3)?,
+                unconfirmed_sats: row.get(4)?,
+            })
+        })?
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    Ok(rows)
+}
+
+/// Minutes between an ISO 8601 timestamp and now, None if SQLite can't
+/// parse it.
+pub fn minutes_since(timestamp: &str) -> Result<Option<f64>> {
+    Ok(settings::connect()?.query_row(
+        "SELECT (julianday('now') - julianday(?1)) * 1440;",
+        params![timestamp],
+        |row| row.get(0),
+    )?)
+}
+
+/// Julian days of ISO 8601 timestamps, so they compare correctly across
+/// formats and time zones. None for the ones SQLite can't parse.
+pub fn julian_days(timestamps: &[&str]) -> Result<Vec<Option<f64>>> {
+    let conn = settings::connect()?;
+    let mut stmt = conn.prepare("SELECT julianday(?1);")?;
+    let mut res = vec![];
+    for timestamp in timestamps {
+        res.push(stmt.query_row(params![timestamp], |row| row.get(0))?);
+    }
+    Ok(res)
+}
@@ -0,0 +1,472 @@
+use crate::{types::*, Result};
+use colored_json::ToColoredJson;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{error::Error, fmt, io, thread, time::Duration};
+use ureq::Agent;
+
+/// Longest wait between two retries, however many attempts were made.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+#[derive(Serialize, Deserialize)]
+pub struct RpcResponse {
+    pub result: Option<Value>,
+    pub error: Option<Value>,
+}
+
+impl RpcResponse {
+    pub fn into_result(self) -> Result<Value> {
+        match self.result {
+            Some(result) => Ok(result),
+            None => Err(self.to_error())?,
+        }
+    }
+
+    pub fn to_error(&self) -> RpcError {
+        match &self.error {
+            Some(error) => RpcError {
+                code: error["code"].as_i64().unwrap_or(0),
+                message: error["message"].as_str().unwrap_or_default().into(),
+                data: error.get("data").cloned(),
+            },
+            None => RpcError {
+                code: -32603,
+                message: "server returned neither result nor error".into(),
+                data: None,
+            },
+        }
+    }
+}
+
+/// Error returned by the server, either as a JSON-RPC error object or as a
+/// non-success HTTP status, in which case code is the status.
+#[derive(Debug)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+    pub data: Option<Value>,
+}
+
+impl RpcError {
+    pub fn to_json(&self) -> Value {
+        let mut error = json!({"code": self.code, "message": self.message});
+        if let Some(data) = &self.data {
+            error["data"] = data.clone();
+        }
+        json!({ "error": error })
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "RPC error {}: {}", self.code, self.message)
+    }
+}
+
+impl Error for RpcError {}
+
+#[derive(Debug)]
+pub struct TransportError(pub String);
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "transport error: {}", self.0)
+    }
+}
+
+impl Error for TransportError {}
+
+/// Returned instead of sending a mutation when dry run is enabled. The plan
+/// describes the call, or holds one entry per call for a batch.
+#[derive(Debug)]
+pub struct DryRun {
+    pub plan: Value,
+}
+
+impl fmt::Display for DryRun {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "dry run, nothing was sent")
+    }
+}
+
+impl Error for DryRun {}
+
+/// Methods that don't change server state and can be safely sent again.
+pub fn is_read_only(method: &str) -> bool {
+    method.starts_with("get_")
+        || method.starts_with("paywall_get_")
+        || matches!(method, "search" | "whoami" | "dashboard")
+}
+
+/// Connection to a BTC Map JSON RPC endpoint. Calls are blocking and the
+/// token, if any, is sent as a bearer token with every request.
+pub struct Client {
+    api_url: String,
+    token: Option<String>,
+    agent: Agent,
+    retries: u32,
+    retry_backoff: f64,
+    retry_mutations: bool,
+    dry_run: bool,
+    verbosity: i64,
+}
+
+impl Client {
+    pub fn new(api_url: impl Into<String>) -> Self {
+        Client {
+            api_url: api_url.into(),
+            token: None,
+            agent: agent(None),
+            retries: 0,
+            retry_backoff: 1.0,
+            retry_mutations: false,
+            dry_run: false,
+            verbosity: 0,
+        }
+    }
+
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// Gives up on a request after this long. No limit when None.
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.agent = agent(timeout);
+        self
+    }
+
+    /// Retries read-only calls on connection failures, timeouts, HTTP 429 and 5xx,
+    /// waiting backoff seconds before the first retry and doubling it after
+    /// every attempt, up to 5 minutes. Negative or NaN backoff counts as 0.
+    pub fn with_retries(mut self, retries: u32, backoff: f64) -> Self {
+        self.retries = retries;
+        self.retry_backoff = backoff.max(0.0);
+        self
+    }
+
+    /// Also retries methods that change server state. They may end up
+    /// applied twice.
+    pub fn with_retry_mutations(mut self, retry_mutations: bool) -> Self {
+        self.retry_mutations = retry_mutations;
+        self
+    }
+
+    /// Returns [`DryRun`] instead of sending calls that change server state.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Prints params and results from 1, full request bodies from 2 and
+    /// full responses from 3.
+    pub fn with_verbosity(mut self, verbosity: i64) -> Self {
+        self.verbosity = verbosity;
+        self
+    }
+
+    pub fn api_url(&self) -> &str {
+        &self.api_url
+    }
+
+    pub fn call(&self, method: &str, params: Value) -> Result<RpcResponse> {
+        if self.dry_run && !is_read_only(method) {
+            Err(DryRun {
+                plan: dry_run_plan(method, &params),
+            })?;
+        }
+        if self.verbosity >= 1 {
+            println!("Calling method {method} with the following params:");
+            println!(
+                "{}",
+                serde_json::to_string(&params)?.to_colored_json_auto()?
+            );
+        }
+        let params = params
+            .as_object()
+            .ok_or("params value is not a valid JSON object")?;
+        let req_body = json!(
+            {"jsonrpc": "2.0", "method": method, "params": params, "id": 1}
+        );
+        if self.verbosity >= 2 {
+            println!("Full request body:");
+            println!(
+                "{}",
+                serde_json::to_string(&req_body)?.to_colored_json_auto()?
+            );
+        }
+        let response: RpcResponse = self.post(&req_body, self.is_retryable(method))?;
+        match self.verbosity {
+            i64::MIN..=0 => {}
+            1..=2 => {
+                if response.result.is_some() {
+                    println!("RPC result:");
+                    println!(
+                        "{}",
+                        serde_json::to_string(&response.result)?.to_colored_json_auto()?
+                    );
+                }
+                if response.error.is_some() {
+                    println!("RPC error:");
+                    println!(
+                        "{}",
+                        serde_json::to_string(&response.error)?.to_colored_json_auto()?
+                    );
+                }
+            }
+            3..=i64::MAX => {
+                println!("RPC response:");
+                println!(
+                    "{}",
+                    serde_json::to_string(&response)?.to_colored_json_auto()?
+                );
+            }
+        };
+        Ok(response)
+    }
+
+    /// Sends several calls in a single JSON-RPC 2.0 batch request. Responses
+    /// are matched by id and returned in the same order as the calls.
+    pub fn call_batch(&self, calls: &[(String, Value)]) -> Result<Vec<RpcResponse>> {
+        if calls.is_empty() {
+            return Ok(vec![]);
+        }
+        if self.dry_run && calls.iter().any(|(method, _)| !is_read_only(method)) {
+            let plans: Vec<Value> = calls
+                .iter()
+                .map(|(method, params)| dry_run_plan(method, params))
+                .collect();
+            Err(DryRun {
+                plan: Value::Array(plans),
+            })?;
+        }
+        let mut req_body = vec![];
+        for (i, (method, params)) in calls.iter().enumerate() {
+            let params = params
+                .as_object()
+                .ok_or(format!("params of {method} is not a valid JSON object"))?;
+            req_body
+                .push(json!({"jsonrpc": "2.0", "method": method, "params": params, "id": i + 1}));
+        }
+        let req_body = Value::Array(req_body);
+        if self.verbosity >= 2 {
+            println!("Full request body:");
+            println!(
+                "{}",
+                serde_json::to_string(&req_body)?.to_colored_json_auto()?
+            );
+        }
+        let retryable = calls.iter().all(|(method, _)| self.is_retryable(method));
+        let response: Value = self.post(&req_body, retryable)?;
+        if self.verbosity >= 3 {
+            println!("RPC response:");
+            println!(
+                "{}",
+                serde_json::to_string(&response)?.to_colored_json_auto()?
+            );
+        }
+        let items = match response {
+            Value::Array(items) => items,
+            // Servers answer with a single error object when the batch itself is invalid
+            other => {
+                let response: RpcResponse = serde_json::from_value(other)?;
+                Err(response.to_error())?
+            }
+        };
+        let mut res: Vec<Option<RpcResponse>> = calls.iter().map(|_| None).collect();
+        for item in items {
+            let index = item["id"].as_u64().unwrap_or(0) as usize;
+            if index == 0 || index > calls.len() {
+                continue;
+            }
+            res[index - 1] = Some(serde_json::from_value(item)?);
+        }
+        Ok(res
+            .into_iter()
+            .map(|it| {
+                it.unwrap_or(RpcResponse {
+                    result: None,
+                    error: Some(
+                        json!({"code": -32603, "message": "server returned no response for this call"}),
+                    ),
+                })
+            })
+            .collect())
+    }
+
+    /// Calls a method with typed params and decodes its result.
+    pub fn request<P: Serialize, R: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: &P,
+    ) -> Result<R> {
+        let result = self
+            .call(method, serde_json::to_value(params)?)?
+            .into_result()?;
+        Ok(serde_json::from_value(result)
+            .map_err(|e| TransportError(format!("unexpected {method} result: {e}")))?)
+    }
+
+    pub fn get_element(&self, params: &GetElementParams) -> Result<Element> {
+        self.request("get_element", params)
+    }
+
+    pub fn set_element_tag(&self, params: &SetElementTagParams) -> Result<Value> {
+        self.request("set_element_tag", params)
+    }
+
+    pub fn remove_element_tag(&self, params: &RemoveElementTagParams) -> Result<Value> {
+        self.request("remove_element_tag", params)
+    }
+
+    pub fn get_area(&self, params: &GetAreaParams) -> Result<Area> {
+        self.request("get_area", params)
+    }
+
+    pub fn set_area_tag(&self, params: &SetAreaTagParams) -> Result<Value> {
+        self.request("set_area_tag", params)
+    }
+
+    pub fn remove_area_tag(&self, params: &RemoveAreaTagParams) -> Result<Value> {
+        self.request("remove_area_tag", params)
+    }
+
+    pub fn get_events(&self, params: &GetEventsParams) -> Result<Vec<Event>> {
+        self.request("get_events", params)
+    }
+
+    pub fn get_event(&self, params: &GetEventParams) -> Result<Event> {
+        self.request("get_event", params)
+    }
+
+    pub fn search(&self, params: &SearchParams) -> Result<Vec<SearchResult>> {
+        self.request("search", params)
+    }
+
+    pub fn get_wallets(&self, params: &GetWalletsParams) -> Result<Vec<Wallet>> {
+        self.request("get_wallets", params)
+    }
+
+    fn is_retryable(&self, method: &str) -> bool {
+        is_read_only(method) || self.retry_mutations
+    }
+
+    fn post<T: DeserializeOwned>(&self, req_body: &Value, retryable: bool) -> Result<T> {
+        let retries = if retryable { self.retries } else { 0 };
+        let mut attempt = 0;
+        loop {
+            let mut request = self
+                .agent
+                .post(&self.api_url)
+                .header("Content-Type", "application/json");
+            if let Some(token) = &self.token {
+                request = request.header("Authorization", format!("Bearer {token}"));
+            }
+            let err: Box<dyn Error> = match request.send_json(req_body) {
+                Ok(mut response) if response.status().as_u16() < 400 => {
+                    return Ok(response
+                        .body_mut()
+                        .read_json()
+                        .map_err(|e| TransportError(format!("malformed response: {e}")))?)
+                }
+                Ok(mut response) => {
+                    let code = response.status().as_u16();
+                    // Some servers send their JSON-RPC error with a 4xx status. The
+                    // status is kept as the code, so exit codes follow it
+                    let error = response
+                        .body_mut()
+                        .read_json::<RpcResponse>()
+                        .ok()
+                        .and_then(|it| it.error);
+                    let err = RpcError {
+                        code: code.into(),
+                        message: match &error {
+                            Some(error) => error["message"]
+                                .as_str()
+                                .map(String::from)
+                                .unwrap_or_else(|| format!("server responded with HTTP {code}")),
+                            None => format!("server responded with HTTP {code}"),
+                        },
+                        data: error,
+                    };
+                    // Only overload and server errors are worth another attempt
+                    if !matches!(code, 429 | 500..=599) {
+                        return Err(err.into());
+                    }
+                    Box::new(err)
+                }
+                Err(e) => {
+                    let transient = is_transient(&e);
+                    let err = TransportError(e.to_string());
+                    // Bad URLs, DNS and TLS failures won't go away on their own
+                    if !transient {
+                        return Err(err.into());
+                    }
+                    Box::new(err)
+                }
+            };
+            if attempt >= retries {
+                return Err(err);
+            }
+            let delay = self.retry_backoff * 2f64.powi(attempt.min(32) as i32);
+            let delay = Duration::try_from_secs_f64(delay)
+                .unwrap_or(MAX_RETRY_DELAY)
+                .min(MAX_RETRY_DELAY);
+            if self.verbosity > 0 {
+                eprintln!(
+                    "Attempt {} failed ({err}), retrying in {:.1}s",
+                    attempt + 1,
+                    delay.as_secs_f64()
+                );
+            }
+            thread::sleep(delay);
+            attempt += 1;
+        }
+    }
+}
+
+/// Password params are redacted, plans end up in terminals and CI logs.
+/// HTTP error statuses come back as responses, so a JSON-RPC error in the
+/// body isn't lost.
+fn agent(timeout: Option<Duration>) -> Agent {
+    Agent::config_builder()
+        .timeout_global(timeout)
+        .http_status_as_error(false)
+        .build()
+        .into()
+}
+
+/// Connection failures and timeouts, the transport errors a retry can fix.
+fn is_transient(e: &ureq::Error) -> bool {
+    match e {
+        ureq::Error::Timeout(_) | ureq::Error::ConnectionFailed => true,
+        ureq::Error::Io(e) => matches!(
+            e.kind(),
+            io::ErrorKind::ConnectionRefused
+                | io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::TimedOut
+                | io::ErrorKind::UnexpectedEof
+                | io::ErrorKind::BrokenPipe
+                | io::ErrorKind::Interrupted
+        ),
+        _ => false,
+    }
+}
+
+fn dry_run_plan(method: &str, params: &Value) -> Value {
+    let mut params = params.clone();
+    if let Some(fields) = params.as_object_mut() {
+        for (name, value) in fields.iter_mut() {
+            if name.contains("password") && !value.is_null() {
+                *value = json!("<redacted>");
+            }
+        }
+    }
+    json!({
+        "dry_run": true,
+        "method": method,
+        "params": params,
+        "mutation": !is_read_only(method),
+    })
+}
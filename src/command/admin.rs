@@ -1,6 +1,13 @@
-use crate::{rpc, settings, Result};
+use crate::{
+    cache,
+    command::invoice,
+    credentials, output,
+    rpc::{self, Print},
+    Result,
+};
 use clap::Args;
-use serde_json::json;
+use serde_json::{json, Value};
+use std::collections::HashSet;
 
 #[derive(Args)]
 pub struct SetApiKeyArgs {
@@ -8,7 +15,7 @@ pub struct SetApiKeyArgs {
 }
 
 pub fn set_api_key(args: &SetApiKeyArgs) -> Result<()> {
-    settings::put_str("password", &args.api_key)?;
+    credentials::set_token(&args.api_key)?;
     println!("api key has been updated");
     Ok(())
 }
@@ -45,14 +52,17 @@ pub fn remove_admin_action(args: &RemoveAdminActionArgs) -> Result<()> {
 pub struct CreateInvoiceArgs {
     pub amount_sats: i64,
     pub description: String,
+    #[command(flatten)]
+    pub invoice: invoice::InvoiceArgs,
 }
 
 pub fn create_invoice(args: &CreateInvoiceArgs) -> Result<()> {
-    rpc::call(
+    let result = rpc::call(
         "create_invoice",
         json!({"amount_sats": args.amount_sats, "description": args.description}),
     )?
-    .print()
+    .into_result()?;
+    invoice::print_invoice(&result, &args.invoice)
 }
 
 #[derive(Args)]
@@ -70,3 +80,197 @@ pub struct GetInvoiceArgs {
 pub fn get_invoice(args: &GetInvoiceArgs) -> Result<()> {
     rpc::call("get_invoice", json!({"id": args.id }))?.print()
 }
+
+#[derive(Args)]
+pub struct InvoicesArgs {
+    /// First invoice id to fetch
+    #[arg(long, default_value_t = 1)]
+    pub from_id: i64,
+    /// Last invoice id to fetch. Without it, ids are walked until a whole batch is not found
+    #[arg(long)]
+    pub to_id: Option<i64>,
+    /// Only count invoices created on or after this date (YYYY-MM-DD or RFC 3339)
+    #[arg(long)]
+    pub since: Option<String>,
+    /// Only count invoices created before this date
+    #[arg(long)]
+    pub until: Option<String>,
+    /// Number of leading description words used to group invoices
+    #[arg(long, default_value_t = 1)]
+    pub prefix_words: usize,
+    /// Print the matching invoices instead of totals
+    #[arg(long)]
+    pub list: bool,
+    /// Report on cached invoices without calling the server
+    #[arg(long, conflicts_with = "refresh")]
+    pub offline: bool,
+    /// Fetch paid and expired invoices again instead of using the cache
+    #[arg(long)]
+    pub refresh: bool,
+    /// Number of get_invoice calls sent in a single batch request
+    #[arg(long, default_value_t = 50)]
+    pub batch_size: usize,
+}
+
+/// Walks invoice ids through get_invoice, caching every invoice, and reports
+/// totals by status and by description prefix. Invoices with a final status
+/// are taken from the cache, so monthly runs only fetch new and unpaid ones.
+pub fn invoices(args: &InvoicesArgs) -> Result<()> {
+    if !args.offline {
+        sync_invoices(args)?;
+    }
+    let bound = |arg: &Option<String>, name: &str| -> Result<Option<f64>> {
+        match arg {
+            Some(it) => Ok(Some(
+                cache::julian_days(&[it])?[0].ok_or(format!("--{name} is not a valid date"))?,
+            )),
+            None => Ok(None),
+        }
+    };
+    let since = bound(&args.since, "since")?;
+    let until = bound(&args.until, "until")?;
+    let invoices: Vec<Value> = cache::invoices()?
+        .into_iter()
+        .filter(|it| {
+            let id = it["id"].as_i64().unwrap_or_default();
+            id >= args.from_id && args.to_id.is_none_or(|to_id| id <= to_id)
+        })
+        .collect();
+    let created_at: Vec<&str> = invoices
+        .iter()
+        .map(|it| it["created_at"].as_str().unwrap_or_default())
+        .collect();
+    let created_at = cache::julian_days(&created_at)?;
+    let invoices: Vec<Value> = invoices
+        .into_iter()
+        .zip(created_at)
+        .filter(|(_, created_at)| {
+            (since.is_none() && until.is_none())
+                || created_at.is_some_and(|it| {
+                    since.is_none_or(|since| it >= since) && until.is_none_or(|until| it < until)
+                })
+        })
+        .map(|(invoice, _)| invoice)
+        .collect();
+    if args.list {
+        let rows: Vec<Value> = invoices
+            .iter()
+            .map(|it| {
+                json!({
+                    "id": it["id"],
+                    "created_at": it["created_at"],
+                    "status": it["status"],
+                    "amount_sats": it["amount_sats"],
+                    "description": it["description"],
+                })
+            })
+            .collect();
+        return output::print(&Value::Array(rows));
+    }
+    let mut rows: Vec<Value> = vec![];
+    let mut add = |group: &str, key: &str, invoice: &Value| {
+        let amount = invoice["amount_sats"].as_i64().unwrap_or_default();
+        match rows
+            .iter_mut()
+            .find(|it| it["group"] == group && it["key"] == key)
+        {
+            Some(row) => {
+                row["count"] = json!(row["count"].as_i64().unwrap_or_default() + 1);
+                row["amount_sats"] =
+                    json!(row["amount_sats"].as_i64().unwrap_or_default() + amount);
+            }
+            None => {
+                rows.push(json!({"group": group, "key": key, "count": 1, "amount_sats": amount}))
+            }
+        }
+    };
+    for invoice in &invoices {
+        add("total", "all", invoice);
+        add(
+            "status",
+            invoice["status"].as_str().unwrap_or("unknown"),
+            invoice,
+        );
+        add(
+            "prefix",
+            &description_prefix(invoice, args.prefix_words),
+            invoice,
+        );
+    }
+    let rank = |row: &Value| {
+        ["total", "status", "prefix"]
+            .iter()
+            .position(|it| row["group"] == *it)
+    };
+    rows.sort_by_key(rank);
+    output::print(&Value::Array(rows))
+}
+
+fn sync_invoices(args: &InvoicesArgs) -> Result<()> {
+    let final_ids: HashSet<i64> = if args.refresh {
+        HashSet::new()
+    } else {
+        cache::invoices()?
+            .iter()
+            .filter(|it| {
+                matches!(
+                    it["status"].as_str(),
+                    Some("paid" | "expired" | "cancelled")
+                )
+            })
+            .filter_map(|it| it["id"].as_i64())
+            .collect()
+    };
+    let batch_size = args.batch_size.max(1) as i64;
+    let mut fetched = 0;
+    let mut start = args.from_id;
+    loop {
+        let end = match args.to_id {
+            Some(to_id) if start > to_id => break,
+            Some(to_id) => to_id.min(start + batch_size - 1),
+            None => start + batch_size - 1,
+        };
+        let ids: Vec<i64> = (start..=end).filter(|id| !final_ids.contains(id)).collect();
+        let calls: Vec<(String, Value)> = ids
+            .iter()
+            .map(|id| ("get_invoice".to_string(), json!({ "id": id })))
+            .collect();
+        let mut found = end - start + 1 - ids.len() as i64;
+        for (id, response) in ids.iter().zip(rpc::call_batch(&calls)?) {
+            match response.result {
+                Some(invoice) => {
+                    cache::put_invoice(&invoice)?;
+                    found += 1;
+                    fetched += 1;
+                }
+                None if rpc::exit_code(&response.to_error()) == rpc::EXIT_NOT_FOUND => {}
+                None => Err(format!("invoice {id}: {}", response.to_error()))?,
+            }
+        }
+        eprintln!("Fetched invoices up to id {end}");
+        if found == 0 && args.to_id.is_none() {
+            break;
+        }
+        start = end + 1;
+    }
+    eprintln!("Fetched {fetched} invoices, the rest came from the cache");
+    Ok(())
+}
+
+/// First words of the description, lowercased, so "Boost element 123 for 30
+/// days" and "Boost element 456 for 7 days" end up in the same group.
+fn description_prefix(invoice: &Value, words: usize) -> String {
+    let prefix = invoice["description"]
+        .as_str()
+        .unwrap_or_default()
+        .split_whitespace()
+        .take(words.max(1))
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase();
+    if prefix.is_empty() {
+        "(none)".into()
+    } else {
+        prefix
+    }
+}
@@ -1,38 +1,703 @@
 use crate::{
-    rpc::{self, RpcResponse},
+    cache,
+    command::{common, shell},
+    geo, imaging, journal, output,
+    rpc::{self, Print},
     Result,
 };
+use btcmap_cli::types::{GetAreaParams, RemoveAreaTagParams, SetAreaTagParams};
 use clap::Args;
+use clap_complete::engine::ArgValueCandidates;
 use serde_json::{json, Map, Value};
 
 #[derive(Args)]
 pub struct GetAreaArgs {
+    #[arg(add = ArgValueCandidates::new(shell::area_candidates))]
     pub id: String,
 }
 
 pub fn get_area(args: &GetAreaArgs) -> Result<()> {
-    rpc::call("get_area", json!({"id": args.id}))
-        .map(|it| RpcResponse {
-            result: it.result.map(|mut it| {
-                it["tags"].as_object_mut().unwrap().remove("geo_json");
-                it
-            }),
-            error: it.error,
-        })?
-        .print()
+    let params = serde_json::to_value(GetAreaParams {
+        id: args.id.clone(),
+    })?;
+    let mut area = rpc::call("get_area", params)?.into_result()?;
+    if let Some(tags) = area["tags"].as_object_mut() {
+        tags.remove("geo_json");
+    }
+    output::print(&area)
+}
+
+#[derive(Args)]
+pub struct ExportArgs {
+    /// Numeric ids or string aliases
+    #[arg(required = true)]
+    #[arg(add = ArgValueCandidates::new(shell::area_candidates))]
+    pub ids: Vec<String>,
+    /// Write the FeatureCollection to this file instead of stdout
+    #[arg(long, conflicts_with = "out_dir")]
+    pub out: Option<String>,
+    /// Write one FeatureCollection per area to this directory, named after the area alias
+    #[arg(long)]
+    pub out_dir: Option<String>,
+}
+
+pub fn export(args: &ExportArgs) -> Result<()> {
+    let areas = fetch_areas(&args.ids)?;
+    match &args.out_dir {
+        Some(dir) => {
+            std::fs::create_dir_all(dir)?;
+            for (id, area) in args.ids.iter().zip(&areas) {
+                let name = file_name(area["tags"]["url_alias"].as_str().unwrap_or(id), area);
+                let path = std::path::Path::new(dir).join(format!("{name}.geojson"));
+                let collection = feature_collection(area_features(area)?);
+                std::fs::write(&path, serde_json::to_string_pretty(&collection)?)?;
+                eprintln!("Saved {}", path.display());
+            }
+        }
+        None => {
+            let mut features = vec![];
+            for area in &areas {
+                features.append(&mut area_features(area)?);
+            }
+            let collection = serde_json::to_string_pretty(&feature_collection(features))?;
+            match &args.out {
+                Some(path) => std::fs::write(path, collection)?,
+                None => println!("{collection}"),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Alias made safe to use as a file name. The alias comes from the server, so
+/// path separators and leading dots are replaced to keep files inside the
+/// target directory.
+fn file_name(alias: &str, area: &Value) -> String {
+    let name: String = alias
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+            _ => '_',
+        })
+        .collect();
+    let name = name.trim_start_matches('.');
+    match name.is_empty() {
+        true => format!("area-{}", area["id"]),
+        false => name.into(),
+    }
+}
+
+/// Fetches full area rows, including geo_json, in a single batch request.
+pub fn fetch_areas(ids: &[String]) -> Result<Vec<Value>> {
+    let calls: Vec<(String, Value)> = ids
+        .iter()
+        .map(|id| ("get_area".to_string(), json!({ "id": id })))
+        .collect();
+    let mut areas = vec![];
+    for (id, response) in ids.iter().zip(rpc::call_batch(&calls)?) {
+        match response.into_result() {
+            Ok(area) => {
+                cache::put_area(&area)?;
+                areas.push(area);
+            }
+            Err(e) => {
+                eprintln!("Failed to fetch area {id}");
+                return Err(e);
+            }
+        }
+    }
+    Ok(areas)
+}
+
+/// Turns an area into GeoJSON features carrying its tags as properties.
+pub fn area_features(area: &Value) -> Result<Vec<Value>> {
+    let mut properties = area["tags"].as_object().cloned().unwrap_or_default();
+    let geo_json = properties
+        .remove("geo_json")
+        .ok_or(format!("area {} has no geo_json tag", area["id"]))?;
+    let geometries: Vec<Value> = match geo_json["type"].as_str() {
+        Some("Feature") => vec![geo_json["geometry"].clone()],
+        Some("FeatureCollection") => geo_json["features"]
+            .as_array()
+            .map(|it| it.iter().map(|it| it["geometry"].clone()).collect())
+            .unwrap_or_default(),
+        _ => vec![geo_json],
+    };
+    Ok(geometries
+        .into_iter()
+        .map(|geometry| {
+            json!({
+                "type": "Feature",
+                "id": area["id"],
+                "properties": properties,
+                "geometry": geometry,
+            })
+        })
+        .collect())
+}
+
+fn feature_collection(features: Vec<Value>) -> Value {
+    json!({ "type": "FeatureCollection", "features": features })
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum SimplifyAlgorithm {
+    /// Douglas-Peucker, tolerance is a distance in degrees
+    DouglasPeucker,
+    /// Visvalingam-Whyatt, tolerance is a triangle area in squared degrees
+    Visvalingam,
+}
+
+#[derive(Args)]
+pub struct SimplifyArgs {
+    /// Numeric id or string alias of the area to simplify
+    #[arg(required_unless_present = "geojson_file")]
+    #[arg(add = ArgValueCandidates::new(shell::area_candidates))]
+    pub id: Option<String>,
+    /// Simplify a local GeoJSON file instead, use - for stdin
+    #[arg(long, conflicts_with = "id")]
+    pub geojson_file: Option<String>,
+    #[arg(long, value_enum, default_value = "douglas-peucker")]
+    pub algorithm: SimplifyAlgorithm,
+    #[arg(long, default_value_t = 0.0001)]
+    pub tolerance: f64,
+    /// Write the simplified GeoJSON to this file
+    #[arg(long)]
+    pub out: Option<String>,
+    /// Replace the geo_json tag of the area with the simplified version
+    #[arg(long, requires = "id")]
+    pub upload: bool,
+}
+
+pub fn simplify(args: &SimplifyArgs) -> Result<()> {
+    let before = match &args.id {
+        Some(id) => fetch_areas(std::slice::from_ref(id))?
+            .remove(0)
+            .get("tags")
+            .and_then(|it| it.get("geo_json"))
+            .cloned()
+            .ok_or(format!("area {id} has no geo_json tag"))?,
+        None => load_geo_json(args.geojson_file.as_deref())?,
+    };
+    let tolerance = args.tolerance;
+    let after = match args.algorithm {
+        SimplifyAlgorithm::DouglasPeucker => {
+            geo::map_rings(&before, &|ring| geo::douglas_peucker(ring, tolerance))?
+        }
+        SimplifyAlgorithm::Visvalingam => {
+            geo::map_rings(&before, &|ring| geo::visvalingam(ring, tolerance))?
+        }
+    };
+    let (polygons_before, polygons_after) = (geo::polygons(&before)?, geo::polygons(&after)?);
+    let (area_before, area_after) = (
+        geo::area_km2(&polygons_before),
+        geo::area_km2(&polygons_after),
+    );
+    let (perimeter_before, perimeter_after) = (
+        geo::perimeter_km(&polygons_before),
+        geo::perimeter_km(&polygons_after),
+    );
+    let drift = |before: f64, after: f64| {
+        if before == 0.0 {
+            0.0
+        } else {
+            (after - before) / before * 100.0
+        }
+    };
+    let after_json = serde_json::to_string(&after)?;
+    output::print(&json!({
+        "vertices_before": geo::vertex_count(&polygons_before),
+        "vertices_after": geo::vertex_count(&polygons_after),
+        "bytes_before": serde_json::to_string(&before)?.len(),
+        "bytes_after": after_json.len(),
+        "area_km2_before": area_before,
+        "area_km2_after": area_after,
+        "area_drift_percent": drift(area_before, area_after),
+        "perimeter_km_before": perimeter_before,
+        "perimeter_km_after": perimeter_after,
+        "perimeter_drift_percent": drift(perimeter_before, perimeter_after),
+    }))?;
+    if let Some(path) = &args.out {
+        std::fs::write(path, &after_json)?;
+    }
+    if let (true, Some(id)) = (args.upload, &args.id) {
+        check_geo_json(&after)?;
+        output::print(&apply_tag(id, "geo_json", Some(after))?.0)?;
+    }
+    Ok(())
+}
+
+#[derive(Args)]
+pub struct GeometryInfoArgs {
+    /// Numeric ids or string aliases
+    #[arg(required_unless_present = "geojson_file")]
+    #[arg(add = ArgValueCandidates::new(shell::area_candidates))]
+    pub ids: Vec<String>,
+    /// Inspect a local GeoJSON file instead, use - for stdin
+    #[arg(long, conflicts_with = "ids")]
+    pub geojson_file: Option<String>,
+    /// Compare the computed bbox with the stored bbox_* tags and fail if any of them is stale
+    #[arg(long, conflicts_with = "geojson_file")]
+    pub check: bool,
+}
+
+pub fn geometry_info(args: &GeometryInfoArgs) -> Result<()> {
+    if args.ids.is_empty() {
+        let geo_json = load_geo_json(args.geojson_file.as_deref())?;
+        return output::print(&geometry_report(&geo_json)?);
+    }
+    let mut reports = vec![];
+    let mut stale = vec![];
+    for (id, area) in args.ids.iter().zip(fetch_areas(&args.ids)?) {
+        let geo_json = area["tags"]
+            .get("geo_json")
+            .ok_or(format!("area {id} has no geo_json tag"))?;
+        let mut report = json!({ "id": area["id"], "alias": area["tags"]["url_alias"] });
+        for (key, value) in geometry_report(geo_json)?.as_object().unwrap() {
+            report[key] = value.clone();
+        }
+        if args.check {
+            let stored: Vec<Option<f64>> = ["bbox_west", "bbox_south", "bbox_east", "bbox_north"]
+                .iter()
+                .map(|it| area["tags"][it].as_f64())
+                .collect();
+            let computed: Vec<f64> = report["bbox"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .filter_map(|it| it.as_f64())
+                .collect();
+            let matches = stored
+                .iter()
+                .zip(&computed)
+                .all(|(stored, computed)| stored.is_some_and(|it| (it - computed).abs() < 1e-6));
+            report["bbox_stored"] = json!(stored);
+            report["bbox_matches"] = json!(matches);
+            if !matches {
+                stale.push(id.clone());
+            }
+        }
+        reports.push(report);
+    }
+    match reports.len() {
+        1 => output::print(&reports[0])?,
+        _ => output::print(&json!(reports))?,
+    }
+    if !stale.is_empty() {
+        Err(format!(
+            "stored bbox is stale for {}, run btcmap-cli area generate-bboxes",
+            stale.join(", ")
+        ))?;
+    }
+    Ok(())
+}
+
+fn geometry_report(geo_json: &Value) -> Result<Value> {
+    let polygons = geo::polygons(geo_json)?;
+    Ok(json!({
+        "bbox": geo::bbox(&polygons),
+        "centroid": geo::centroid(&polygons),
+        "area_km2": geo::area_km2(&polygons),
+        "polygons": polygons.len(),
+        "rings": polygons.iter().map(|it| it.len()).sum::<usize>(),
+        "vertices": geo::vertex_count(&polygons),
+    }))
+}
+
+#[derive(Args)]
+pub struct LocateArgs {
+    #[clap(allow_hyphen_values = true)]
+    #[arg(long)]
+    pub lat: f64,
+    #[clap(allow_hyphen_values = true)]
+    #[arg(long)]
+    pub lon: f64,
+    /// Fetch these areas (ids or aliases) and add them to the local cache first
+    #[arg(long, num_args = 1..)]
+    #[arg(add = ArgValueCandidates::new(shell::area_candidates))]
+    pub fetch: Vec<String>,
+    /// Fetch every area from the server into the local cache first, walking ids in batches until --empty-batches in a row are not found
+    #[arg(long, conflicts_with = "fetch")]
+    pub fetch_all: bool,
+    /// Number of get_area calls per batch request with --fetch-all
+    #[arg(long, default_value_t = 50, requires = "fetch_all")]
+    pub batch_size: u32,
+    /// Consecutive batches without any area after which --fetch-all stops. Raise it if ids have larger gaps
+    #[arg(long, default_value_t = 5, requires = "fetch_all", value_parser = clap::value_parser!(u32).range(1..))]
+    pub empty_batches: u32,
+    /// Search exported GeoJSON files or directories of them instead of the local cache
+    #[arg(long, conflicts_with_all = ["fetch", "fetch_all"])]
+    pub from: Vec<String>,
+}
+
+pub fn locate(args: &LocateArgs) -> Result<()> {
+    if !args.fetch.is_empty() {
+        fetch_areas(&args.fetch)?;
+    }
+    if args.fetch_all {
+        fetch_all_areas(args.batch_size, args.empty_batches)?;
+    }
+    let features = if args.from.is_empty() {
+        let features = cached_features()?;
+        if features.is_empty() {
+            Err("no cached areas, run btcmap-cli area locate --fetch-all or pass --from <exported files>")?;
+        }
+        features
+    } else {
+        exported_features(&args.from)?
+    };
+    output::print(&json!(containing_areas(&features, args.lat, args.lon)))
+}
+
+/// There is no method listing all areas, so ids are walked from 1 until
+/// several whole batches in a row come back not found, caching every area on
+/// the way. Deleted areas leave gaps in the ids, one empty batch isn't enough.
+fn fetch_all_areas(batch_size: u32, empty_batches: u32) -> Result<()> {
+    let batch_size = batch_size.max(1) as i64;
+    let mut fetched = 0;
+    let mut empty = 0;
+    let mut last_found = 0;
+    let mut start = 1;
+    loop {
+        let end = start + batch_size - 1;
+        let calls: Vec<(String, Value)> = (start..=end)
+            .map(|id| ("get_area".to_string(), json!({ "id": id.to_string() })))
+            .collect();
+        let mut found = 0;
+        for (id, response) in (start..=end).zip(rpc::call_batch(&calls)?) {
+            match response.result {
+                Some(area) => {
+                    cache::put_area(&area)?;
+                    found += 1;
+                    last_found = id;
+                }
+                None if rpc::exit_code(&response.to_error()) == rpc::EXIT_NOT_FOUND => {}
+                None => Err(format!("area {id}: {}", response.to_error()))?,
+            }
+        }
+        eprintln!("Fetched areas up to id {end}");
+        fetched += found;
+        empty = if found == 0 { empty + 1 } else { 0 };
+        if empty >= empty_batches {
+            eprintln!(
+                "Cached {fetched} areas, the highest id found is {last_found}, scanned ids up to {end}"
+            );
+            return Ok(());
+        }
+        start = end + 1;
+    }
+}
+
+pub fn cached_features() -> Result<Vec<Value>> {
+    let mut features = vec![];
+    for area in cache::areas()? {
+        if area["tags"].get("geo_json").is_some() {
+            features.append(&mut area_features(&area)?);
+        }
+    }
+    Ok(features)
+}
+
+/// Areas whose geometry contains the point, ordered by type and then from the
+/// smallest to the largest so the most specific match comes first. Features
+/// that aren't polygons are skipped with a warning.
+pub fn containing_areas(features: &[Value], lat: f64, lon: f64) -> Vec<Value> {
+    let mut res = vec![];
+    for feature in features {
+        let polygons = match geo::polygons(feature) {
+            Ok(polygons) => polygons,
+            Err(e) => {
+                eprintln!("Warning: skipping area {}: {e}", feature["id"]);
+                continue;
+            }
+        };
+        if !geo::contains(&polygons, [lon, lat]) {
+            continue;
+        }
+        let properties = &feature["properties"];
+        res.push(json!({
+            "id": feature["id"],
+            "alias": properties["url_alias"],
+            "name": properties["name"],
+            "type": properties["type"],
+            "area_km2": geo::area_km2(&polygons),
+        }));
+    }
+    res.sort_by(|a, b| {
+        a["type"].as_str().cmp(&b["type"].as_str()).then(
+            a["area_km2"]
+                .as_f64()
+                .unwrap_or(0.0)
+                .total_cmp(&b["area_km2"].as_f64().unwrap_or(0.0)),
+        )
+    });
+    res.dedup_by(|a, b| a["id"] == b["id"] && !a["id"].is_null());
+    res
+}
+
+fn exported_features(paths: &[String]) -> Result<Vec<Value>> {
+    let mut files = vec![];
+    for path in paths {
+        let path = std::path::Path::new(path);
+        if path.is_dir() {
+            for entry in std::fs::read_dir(path)? {
+                let entry = entry?.path();
+                if entry
+                    .extension()
+                    .is_some_and(|it| it == "geojson" || it == "json")
+                {
+                    files.push(entry);
+                }
+            }
+        } else {
+            files.push(path.to_path_buf());
+        }
+    }
+    let mut features = vec![];
+    for file in files {
+        let geo_json: Value = serde_json::from_str(&std::fs::read_to_string(&file)?)
+            .map_err(|e| format!("{} is not a valid JSON value ({e})", file.display()))?;
+        match geo_json["type"].as_str() {
+            Some("FeatureCollection") => {
+                features.extend(geo_json["features"].as_array().cloned().unwrap_or_default())
+            }
+            _ => features.push(geo_json),
+        }
+    }
+    Ok(features)
+}
+
+#[derive(Args)]
+pub struct SnapshotArgs {
+    /// Numeric ids or string aliases
+    #[arg(required = true)]
+    #[arg(add = ArgValueCandidates::new(shell::area_candidates))]
+    pub ids: Vec<String>,
+}
+
+pub fn snapshot(args: &SnapshotArgs) -> Result<()> {
+    let areas = fetch_areas(&args.ids)?;
+    if rpc::is_dry_run() {
+        eprintln!("Dry run, no snapshots were saved");
+        return Ok(());
+    }
+    let mut res = vec![];
+    for area in areas {
+        let snapshot = cache::put_area_snapshot(&area)?;
+        res.push(json!({
+            "snapshot": snapshot.id,
+            "area_id": area["id"],
+            "alias": area["tags"]["url_alias"],
+            "taken_at": snapshot.taken_at,
+        }));
+    }
+    output::print(&json!(res))
+}
+
+#[derive(Args)]
+pub struct DiffArgs {
+    /// Numeric id or string alias
+    #[arg(add = ArgValueCandidates::new(shell::area_candidates))]
+    pub id: String,
+    /// Compare with an area JSON file (get-area output) or an exported Feature/FeatureCollection
+    #[arg(long, conflicts_with = "snapshot")]
+    pub file: Option<String>,
+    /// Compare with a specific snapshot instead of the latest one
+    #[arg(long)]
+    pub snapshot: Option<i64>,
+}
+
+pub fn diff(args: &DiffArgs) -> Result<()> {
+    let current = fetch_areas(std::slice::from_ref(&args.id))?.remove(0);
+    let baseline = match &args.file {
+        Some(path) => area_from_file(path, &current["id"])?,
+        None => {
+            let area_id = current["id"].as_i64().ok_or("area has no numeric id")?;
+            let snapshots = cache::area_snapshots(area_id)?;
+            let snapshot = match args.snapshot {
+                Some(id) => snapshots.into_iter().find(|it| it.id == id),
+                None => snapshots.into_iter().last(),
+            };
+            snapshot
+                .ok_or(format!(
+                    "no snapshot found, run btcmap-cli area snapshot {} first",
+                    args.id
+                ))?
+                .area
+        }
+    };
+    output::print(&json!(tag_changes(&baseline["tags"], &current["tags"])?))
+}
+
+#[derive(Args)]
+pub struct HistoryArgs {
+    /// Numeric id or string alias
+    #[arg(add = ArgValueCandidates::new(shell::area_candidates))]
+    pub id: String,
+}
+
+/// Lists saved snapshots of an area with the tags that changed since the
+/// previous one.
+pub fn history(args: &HistoryArgs) -> Result<()> {
+    let area_id = match args.id.parse::<i64>() {
+        Ok(id) => id,
+        Err(_) => cache::areas()?
+            .iter()
+            .find(|it| it["tags"]["url_alias"].as_str() == Some(&args.id))
+            .and_then(|it| it["id"].as_i64())
+            .ok_or(format!(
+                "area {} is not cached, use its numeric id",
+                args.id
+            ))?,
+    };
+    let snapshots = cache::area_snapshots(area_id)?;
+    let mut res = vec![];
+    for (i, snapshot) in snapshots.iter().enumerate() {
+        let changed: Vec<String> = match i {
+            0 => vec![],
+            _ => tag_changes(&snapshots[i - 1].area["tags"], &snapshot.area["tags"])?
+                .iter()
+                .map(|it| {
+                    format!(
+                        "{} {}",
+                        it["change"].as_str().unwrap_or_default(),
+                        it["tag"].as_str().unwrap_or_default()
+                    )
+                })
+                .collect(),
+        };
+        res.push(json!({
+            "snapshot": snapshot.id,
+            "taken_at": snapshot.taken_at,
+            "tags": snapshot.area["tags"].as_object().map(|it| it.len()).unwrap_or(0),
+            "changes": changed.join(", "),
+        }));
+    }
+    output::print(&json!(res))
+}
+
+/// Added, removed and changed tags between two tag maps. The geometry is
+/// summarized by vertex count and bbox instead of being dumped.
+fn tag_changes(old: &Value, new: &Value) -> Result<Vec<Value>> {
+    let empty = Map::new();
+    let old = old.as_object().unwrap_or(&empty);
+    let new = new.as_object().unwrap_or(&empty);
+    let mut res = vec![];
+    for (tag, old_value) in old {
+        match new.get(tag) {
+            None => res.push(tag_change(tag, "removed", Some(old_value), None)?),
+            Some(new_value) if !same_value(tag, old_value, new_value) => res.push(tag_change(
+                tag,
+                "changed",
+                Some(old_value),
+                Some(new_value),
+            )?),
+            _ => {}
+        }
+    }
+    for (tag, new_value) in new {
+        if !old.contains_key(tag) {
+            res.push(tag_change(tag, "added", None, Some(new_value))?);
+        }
+    }
+    Ok(res)
+}
+
+/// Geometries are compared by their polygons, so a Feature and its bare
+/// geometry (as exports store it) count as the same shape.
+fn same_value(tag: &str, old: &Value, new: &Value) -> bool {
+    if old == new {
+        return true;
+    }
+    match (tag, geo::polygons(old), geo::polygons(new)) {
+        ("geo_json", Ok(old), Ok(new)) => old == new,
+        _ => false,
+    }
+}
+
+fn tag_change(tag: &str, change: &str, old: Option<&Value>, new: Option<&Value>) -> Result<Value> {
+    if tag != "geo_json" {
+        return Ok(json!({"tag": tag, "change": change, "old": old, "new": new}));
+    }
+    let summary = |geo_json: Option<&Value>| -> Result<Option<(usize, [f64; 4])>> {
+        match geo_json {
+            Some(geo_json) => {
+                let polygons = geo::polygons(geo_json)?;
+                Ok(Some((geo::vertex_count(&polygons), geo::bbox(&polygons))))
+            }
+            None => Ok(None),
+        }
+    };
+    let (old, new) = (summary(old)?, summary(new)?);
+    let describe = |it: Option<(usize, [f64; 4])>| {
+        it.map(|(vertices, bbox)| format!("{vertices} vertices, bbox {bbox:?}"))
+    };
+    let mut res = json!({"tag": tag, "change": change, "old": describe(old), "new": describe(new)});
+    if let (Some((old_vertices, old_bbox)), Some((new_vertices, new_bbox))) = (old, new) {
+        res["vertex_delta"] = json!(new_vertices as i64 - old_vertices as i64);
+        res["bbox_delta"] = json!([
+            new_bbox[0] - old_bbox[0],
+            new_bbox[1] - old_bbox[1],
+            new_bbox[2] - old_bbox[2],
+            new_bbox[3] - old_bbox[3],
+        ]);
+    }
+    Ok(res)
+}
+
+/// Reads an area baseline from get-area style JSON or exported GeoJSON.
+fn area_from_file(path: &str, id: &Value) -> Result<Value> {
+    let value: Value = serde_json::from_str(&std::fs::read_to_string(path)?)
+        .map_err(|e| format!("{path} is not a valid JSON value ({e})"))?;
+    let features = match value["type"].as_str() {
+        Some("FeatureCollection") => {
+            let features = value["features"].as_array().cloned().unwrap_or_default();
+            let matching: Vec<Value> = features
+                .iter()
+                .filter(|it| &it["id"] == id)
+                .cloned()
+                .collect();
+            if matching.is_empty() {
+                features.into_iter().take(1).collect()
+            } else {
+                matching
+            }
+        }
+        Some("Feature") => vec![value],
+        _ => return Ok(value),
+    };
+    let feature = features.first().ok_or(format!("{path} has no features"))?;
+    let mut tags = feature["properties"]
+        .as_object()
+        .cloned()
+        .unwrap_or_default();
+    // Export writes one feature per polygon group of a FeatureCollection area
+    let geo_json = match features.len() {
+        1 => feature["geometry"].clone(),
+        _ => feature_collection(features.clone()),
+    };
+    tags.insert("geo_json".into(), geo_json);
+    Ok(json!({ "id": feature["id"], "tags": tags }))
 }
 
 #[derive(Args)]
 pub struct AddAreaArgs {
     #[arg(long)]
     pub alias: String,
-    #[arg(long = "geojson")]
-    pub geojson: String,
+    #[arg(long = "geojson", required_unless_present = "geojson_file")]
+    pub geojson: Option<String>,
+    /// Read GeoJSON from a file instead, use - for stdin
+    #[arg(long = "geojson-file", conflicts_with = "geojson")]
+    pub geojson_file: Option<String>,
 }
 
 pub fn add_area(args: &AddAreaArgs) -> Result<()> {
-    let geo_json: Value = serde_json::from_str(&args.geojson)
-        .map_err(|e| format!("invalid --geojson: not a valid JSON value ({e})"))?;
+    let geo_json = match (&args.geojson, &args.geojson_file) {
+        (Some(geojson), _) => serde_json::from_str(geojson)
+            .map_err(|e| format!("invalid --geojson: not a valid JSON value ({e})"))?,
+        (None, file) => load_geo_json(file.as_deref())?,
+    };
+    check_geo_json(&geo_json)?;
     let mut tags = Map::new();
     tags.insert("url_alias".into(), Value::String(args.alias.clone()));
     tags.insert("name".into(), Value::String(args.alias.clone()));
@@ -42,6 +707,7 @@ pub fn add_area(args: &AddAreaArgs) -> Result<()> {
 
 #[derive(Args)]
 pub struct SetAreaTagArgs {
+    #[arg(add = ArgValueCandidates::new(shell::area_candidates))]
     pub id: String,
     pub name: String,
     pub value: String,
@@ -49,35 +715,128 @@ pub struct SetAreaTagArgs {
 
 pub fn set_area_tag(args: &SetAreaTagArgs) -> Result<()> {
     let value: Value = serde_json::from_str(&args.value)?;
-    rpc::call(
-        "set_area_tag",
-        json!({"id": args.id,"name": args.name, "value": value}),
-    )?
-    .print()
+    if args.name == "geo_json" {
+        check_geo_json(&value)?;
+    }
+    output::print(&apply_tag(&args.id, &args.name, Some(value))?.0)
+}
+
+/// Sets a tag, or removes it when the value is missing, and records the
+/// previous value in the local journal so the change can be undone.
+/// Returns the server result and the journal entry id.
+pub fn apply_tag(id: &str, name: &str, value: Option<Value>) -> Result<(Value, i64)> {
+    let (area_id, old_value) = current_tag(id, name)?;
+    let client = rpc::client()?;
+    let result = match &value {
+        Some(value) => client.set_area_tag(&SetAreaTagParams {
+            id: id.into(),
+            name: name.into(),
+            value: value.clone(),
+        })?,
+        None => client.remove_area_tag(&RemoveAreaTagParams {
+            id: id.into(),
+            tag: name.into(),
+        })?,
+    };
+    let entry = journal::record(
+        "area",
+        &area_id.to_string(),
+        name,
+        old_value.as_ref(),
+        value.as_ref(),
+    )?;
+    Ok((result, entry))
+}
+
+/// Numeric id of an area and the current value of one of its tags. get_area
+/// has no field selection, but the rest of the area, geometry included, is
+/// dropped right away instead of going to the area cache or the journal.
+pub fn current_tag(id: &str, name: &str) -> Result<(i64, Option<Value>)> {
+    let mut area = rpc::client()?.get_area(&GetAreaParams { id: id.into() })?;
+    Ok((area.id, area.tags.remove(name)))
 }
 
 #[derive(Args)]
 pub struct RemoveAreaTagArgs {
+    #[arg(add = ArgValueCandidates::new(shell::area_candidates))]
     pub id: String,
     pub tag: String,
 }
 
 pub fn remove_area_tag(args: &RemoveAreaTagArgs) -> Result<()> {
-    rpc::call("remove_area_tag", json!({"id": args.id,"tag": args.tag}))?.print()
+    output::print(&apply_tag(&args.id, &args.tag, None)?.0)
 }
 
 #[derive(Args)]
 pub struct SetAreaImageArgs {
     #[arg(long)]
+    #[arg(add = ArgValueCandidates::new(shell::area_candidates))]
     pub area: String,
+    /// Image slot, square images must have equal width and height
     #[arg(long, default_value = "square")]
     pub r#type: String,
+    /// Png, jpeg or webp file, the type is detected from its content
     #[arg(long)]
     pub image: String,
+    /// Center-crop images that aren't square instead of rejecting them
+    #[arg(long)]
+    pub crop: bool,
+    /// Downscale so that neither side exceeds this many pixels
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_side: Option<u32>,
+    /// Re-encode to this format before upload
+    #[arg(long)]
+    pub format: Option<imaging::Format>,
+    /// Jpeg quality used when re-encoding
+    #[arg(long, default_value_t = 85)]
+    pub quality: u8,
+    /// Refuse to upload files larger than this many bytes
+    #[arg(long, default_value_t = 2_000_000)]
+    pub max_bytes: usize,
 }
 
 pub fn set_area_image(args: &SetAreaImageArgs) -> Result<()> {
-    let bytes = std::fs::read(&args.image)?;
+    let mut bytes = std::fs::read(&args.image)?;
+    let format = imaging::detect(&bytes)?;
+    let mut image = imaging::decode(&bytes, format)?;
+    let (width, height) = (image.width(), image.height());
+    let square = args.r#type == "square";
+    let mut changed = false;
+    if square && width != height {
+        if !args.crop {
+            Err(format!(
+                "{} is {width}x{height}, square images must have equal sides, pass --crop to cut out the center",
+                args.image
+            ))?;
+        }
+        image = imaging::crop_square(&image);
+        changed = true;
+    }
+    if let Some(max_side) = args.max_side {
+        if width > max_side || height > max_side {
+            image = imaging::downscale(image, max_side);
+            changed = true;
+        }
+    }
+    let target = args.format.unwrap_or(format);
+    if changed || target != format {
+        bytes = imaging::encode(&image, target, args.quality)?;
+        eprintln!(
+            "Re-encoded {width}x{height} {} to {}x{} {}, {} bytes",
+            format.name(),
+            image.width(),
+            image.height(),
+            target.name(),
+            bytes.len()
+        );
+    }
+    if bytes.len() > args.max_bytes {
+        Err(format!(
+            "image is {} bytes, the limit is {}, use --max-side or --format jpeg to make it smaller",
+            bytes.len(),
+            args.max_bytes
+        ))?;
+    }
     let image_base64 = base64::Engine::encode(&base64::engine::general_purpose::STANDARD, &bytes);
     rpc::call(
         "set_area_image",
@@ -101,3 +860,103 @@ pub fn generate_bboxes() -> Result<()> {
 pub fn generate_area_icons() -> Result<()> {
     rpc::call("generate_area_icons", json!({}))?.print()
 }
+
+/// Reads GeoJSON from a file, or stdin when the path is missing or -.
+pub fn load_geo_json(path: Option<&str>) -> Result<Value> {
+    let input = common::read_file_or_stdin(path)?;
+    Ok(serde_json::from_str(&input).map_err(|e| {
+        format!(
+            "{} is not a valid JSON value ({e})",
+            path.filter(|it| *it != "-").unwrap_or("stdin")
+        )
+    })?)
+}
+
+/// Runs the local GeoJSON validator, prints warnings to stderr and fails on
+/// errors so nothing broken reaches the server.
+pub fn check_geo_json(geo_json: &Value) -> Result<()> {
+    let issues = geo::validate(geo_json);
+    for issue in issues.iter().filter(|it| it.warning) {
+        eprintln!("warning: {}: {}", issue.path, issue.message);
+    }
+    let errors: Vec<String> = issues
+        .iter()
+        .filter(|it| !it.warning)
+        .map(|it| format!("{}: {}", it.path, it.message))
+        .collect();
+    if !errors.is_empty() {
+        Err(format!(
+            "invalid GeoJSON, {} error(s):\n{}",
+            errors.len(),
+            errors.join("\n")
+        ))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn area(geo_json: Value) -> Value {
+        json!({
+            "id": 7,
+            "tags": {
+                "name": "Test area",
+                "url_alias": "test-area",
+                "geo_json": geo_json,
+            }
+        })
+    }
+
+    fn square(size: f64) -> Value {
+        json!({
+            "type": "Polygon",
+            "coordinates": [[[0.0, 0.0], [size, 0.0], [size, size], [0.0, size], [0.0, 0.0]]]
+        })
+    }
+
+    /// Exports an area the way area export does and reads it back as a diff baseline.
+    fn exported(area: &Value, name: &str) -> Value {
+        let path =
+            std::env::temp_dir().join(format!("btcmap-cli-{name}-{}.geojson", std::process::id()));
+        let collection = feature_collection(area_features(area).unwrap());
+        std::fs::write(&path, collection.to_string()).unwrap();
+        let res = area_from_file(path.to_str().unwrap(), &area["id"]).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        res
+    }
+
+    #[test]
+    fn unchanged_export_has_no_changes() {
+        let area = area(json!({"type": "Feature", "properties": {}, "geometry": square(1.0)}));
+        let baseline = exported(&area, "unchanged");
+        assert!(tag_changes(&baseline["tags"], &area["tags"])
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn unchanged_feature_collection_export_has_no_changes() {
+        let feature = |size| json!({"type": "Feature", "properties": {}, "geometry": square(size)});
+        let area =
+            area(json!({"type": "FeatureCollection", "features": [feature(1.0), feature(2.0)]}));
+        let baseline = exported(&area, "collection");
+        assert!(tag_changes(&baseline["tags"], &area["tags"])
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn changed_geometry_is_summarized() {
+        let baseline = exported(&area(square(1.0)), "changed");
+        let mut current = area(square(2.0));
+        current["tags"]["name"] = json!("Renamed");
+        let changes = tag_changes(&baseline["tags"], &current["tags"]).unwrap();
+        assert_eq!(changes.len(), 2);
+        let geo_json = changes.iter().find(|it| it["tag"] == "geo_json").unwrap();
+        assert_eq!(geo_json["change"], "changed");
+        assert_eq!(geo_json["vertex_delta"], 0);
+        assert_eq!(geo_json["bbox_delta"], json!([0.0, 0.0, 1.0, 1.0]));
+    }
+}
@@ -1,4 +1,8 @@
-use crate::{rpc, settings, verbosity, Result};
+use crate::{
+    credentials, prompt,
+    rpc::{self, Print},
+    Result,
+};
 use clap::Args;
 use serde_json::json;
 
@@ -6,37 +10,46 @@ use serde_json::json;
 pub struct SignUpArgs {
     #[arg(long)]
     pub user: String,
+    /// Prompted for (twice) when omitted. Avoid passing it here, it ends up in shell history
     #[arg(long)]
-    pub password: String,
+    pub password: Option<String>,
+    /// Read the password from the first line of stdin
+    #[arg(long, conflicts_with = "password")]
+    pub password_stdin: bool,
     #[arg(long)]
     pub label: Option<String>,
 }
 
 pub fn sign_up(args: &SignUpArgs) -> Result<()> {
+    let password = match &args.password {
+        Some(password) => password.clone(),
+        None if args.password_stdin => prompt::stdin_secrets(1)?.remove(0),
+        None => prompt::secret("Password: ", true)?,
+    };
     let mut params = json!({
         "username": args.user,
-        "password": args.password,
+        "password": password,
     });
     if let Some(label) = &args.label {
         params["label"] = json!(label);
     }
-    let response = rpc::call_anon("signup", params)?;
-    if let Some(result) = response.result {
-        let api_key = result["api_key"].as_str().unwrap_or_default().to_string();
-        settings::put_str("password", &api_key)?;
-        println!("You are now logged in as {}", args.user);
-    } else if verbosity() == 0 {
-        eprintln!("Signup failed, use verbose mode to see more details");
-    } else {
-        eprintln!("Signup failed")
-    }
+    let result = rpc::call_anon("signup", params)?.into_result()?;
+    let api_key = result["api_key"]
+        .as_str()
+        .ok_or("signup response is missing api_key")?;
+    credentials::set_token(api_key)?;
+    println!("You are now logged in as {}", args.user);
     Ok(())
 }
 
 #[derive(Args)]
 pub struct SignInArgs {
     pub username: String,
-    pub password: String,
+    /// Prompted for when omitted. Avoid passing it here, it ends up in shell history
+    pub password: Option<String>,
+    /// Read the password from the first line of stdin
+    #[arg(long, conflicts_with = "password")]
+    pub password_stdin: bool,
     #[arg(long)]
     pub label: Option<String>,
     #[arg(long = "role", value_name = "ROLE", action = clap::ArgAction::Append)]
@@ -48,26 +61,27 @@ pub fn sign_in(args: &SignInArgs) -> Result<()> {
         .label
         .clone()
         .unwrap_or_else(|| format!("btcmap-cli ({})", hostname()));
+    let password = match &args.password {
+        Some(password) => password.clone(),
+        None if args.password_stdin => prompt::stdin_secrets(1)?.remove(0),
+        None => prompt::secret("Password: ", false)?,
+    };
     let mut params = json!({
         "username": args.username,
-        "password": args.password,
+        "password": password,
         "label": label,
     });
     if !args.roles.is_empty() {
         params["roles"] = json!(args.roles);
     }
-    let response = rpc::call_anon("signin", params)?;
-    if let Some(result) = response.result {
-        let api_key = result["api_key"].as_str().unwrap().to_string();
-        settings::put_str("password", &api_key)?;
-        println!("You are now logged in as {}", args.username);
-        if !args.roles.is_empty() {
-            println!("Token scope: {}", args.roles.join(", "));
-        }
-    } else if verbosity() == 0 {
-        eprintln!("Signin failed, use verbose mode to see more details");
-    } else {
-        eprintln!("Signin failed")
+    let result = rpc::call_anon("signin", params)?.into_result()?;
+    let api_key = result["api_key"]
+        .as_str()
+        .ok_or("signin response is missing api_key")?;
+    credentials::set_token(api_key)?;
+    println!("You are now logged in as {}", args.username);
+    if !args.roles.is_empty() {
+        println!("Token scope: {}", args.roles.join(", "));
     }
     Ok(())
 }
@@ -76,19 +90,39 @@ pub fn sign_in(args: &SignInArgs) -> Result<()> {
 pub struct ChangePasswordArgs {
     #[arg(long)]
     pub user: String,
+    /// Prompted for when omitted
     #[arg(long)]
-    pub old: String,
+    pub old: Option<String>,
+    /// Prompted for (twice) when omitted
     #[arg(long)]
-    pub new: String,
+    pub new: Option<String>,
+    /// Read the old password from the first line of stdin and the new one from the second
+    #[arg(long, conflicts_with_all = ["old", "new"])]
+    pub password_stdin: bool,
 }
 
 pub fn change_password(args: &ChangePasswordArgs) -> Result<()> {
+    let (old, new) = if args.password_stdin {
+        let mut lines = prompt::stdin_secrets(2)?;
+        let new = lines.remove(1);
+        (lines.remove(0), new)
+    } else {
+        let old = match &args.old {
+            Some(old) => old.clone(),
+            None => prompt::secret("Old password: ", false)?,
+        };
+        let new = match &args.new {
+            Some(new) => new.clone(),
+            None => prompt::secret("New password: ", true)?,
+        };
+        (old, new)
+    };
     rpc::call(
         "change_password",
         json!({
             "username": args.user,
-            "old_password": args.old,
-            "new_password": args.new,
+            "old_password": old,
+            "new_password": new,
         }),
     )?
     .print()
@@ -116,7 +150,7 @@ pub struct SignOutArgs {}
 pub fn sign_out(_args: &SignOutArgs) -> Result<()> {
     let response = rpc::call("signout", json!({}))?;
     response.print()?;
-    settings::put_str("password", "")?;
+    credentials::set_token("")?;
     println!("Signed out");
     Ok(())
 }
@@ -1,6 +1,11 @@
-use crate::{rpc, Result};
+use crate::{
+    rpc::{self, Print},
+    Result,
+};
+use btcmap_cli::types::SearchParams;
 use clap::Args;
 use serde_json::{json, Map, Value};
+use std::io::Read;
 
 #[derive(Args)]
 pub struct SearchArgs {
@@ -8,11 +13,19 @@ pub struct SearchArgs {
 }
 
 pub fn search(args: &SearchArgs) -> Result<()> {
-    rpc::call("search", json!({"query": args.query}))?.print()
+    print_search(args, None)
 }
 
 pub fn search_typed(args: &SearchArgs, search_type: &str) -> Result<()> {
-    rpc::call("search", json!({"query": args.query, "type": search_type}))?.print()
+    print_search(args, Some(search_type))
+}
+
+fn print_search(args: &SearchArgs, kind: Option<&str>) -> Result<()> {
+    let params = serde_json::to_value(SearchParams {
+        query: args.query.clone(),
+        kind: kind.map(String::from),
+    })?;
+    rpc::call("search", params)?.print()
 }
 
 #[derive(Args)]
@@ -35,8 +48,73 @@ pub struct CustomArgs {
 
 pub fn rpc(args: &CustomArgs) -> Result<()> {
     let params: Value = match &args.params {
-        Some(params) => serde_json::from_str(&params)?,
+        Some(params) => serde_json::from_str(params)?,
         None => Value::Object(Map::new()),
     };
     rpc::call(&args.method, params)?.print()
 }
+
+#[derive(Args)]
+pub struct BatchArgs {
+    /// File with one {"method": ..., "params": {...}} object per line. Reads stdin when omitted or -
+    pub file: Option<String>,
+    /// Maximum number of calls sent in a single batch request
+    #[arg(long, default_value_t = 100)]
+    pub batch_size: usize,
+}
+
+pub fn batch(args: &BatchArgs) -> Result<()> {
+    let input = read_file_or_stdin(args.file.as_deref())?;
+    let mut calls = vec![];
+    for (i, line) in input.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let call: Value = serde_json::from_str(line)
+            .map_err(|e| format!("line {}: not a valid JSON value ({e})", i + 1))?;
+        let method = call["method"]
+            .as_str()
+            .ok_or(format!("line {}: method is missing", i + 1))?;
+        let params = match call.get("params") {
+            Some(params) if params.is_object() => params.clone(),
+            None | Some(Value::Null) => Value::Object(Map::new()),
+            Some(_) => Err(format!("line {}: params must be a JSON object", i + 1))?,
+        };
+        calls.push((method.to_string(), params));
+    }
+    let mut failed = 0;
+    let batch_size = if rpc::is_dry_run() {
+        calls.len()
+    } else {
+        args.batch_size
+    };
+    for chunk in calls.chunks(batch_size.max(1)) {
+        for response in rpc::call_batch(chunk)? {
+            let line = match (response.result, response.error) {
+                (Some(result), _) => json!({ "result": result }),
+                (None, error) => {
+                    failed += 1;
+                    json!({ "error": error })
+                }
+            };
+            println!("{}", serde_json::to_string(&line)?);
+        }
+    }
+    if failed > 0 {
+        Err(format!("{failed} of {} calls failed", calls.len()))?;
+    }
+    Ok(())
+}
+
+/// Reads a whole file, or stdin when the path is missing or -.
+pub fn read_file_or_stdin(path: Option<&str>) -> Result<String> {
+    match path {
+        None | Some("-") => {
+            let mut buf = String::new();
+            std::io::stdin().read_to_string(&mut buf)?;
+            Ok(buf)
+        }
+        Some(path) => Ok(std::fs::read_to_string(path)?),
+    }
+}
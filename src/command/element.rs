@@ -1,6 +1,13 @@
-use crate::{rpc, Result};
+use crate::{
+    command::{common, invoice},
+    csv, journal, output,
+    rpc::{self, Print},
+    Result,
+};
+use btcmap_cli::types::{GetElementParams, RemoveElementTagParams, SetElementTagParams};
 use clap::Args;
-use serde_json::{json, Value};
+use serde_json::{json, Map, Value};
+use std::{collections::HashMap, io::Write};
 
 #[derive(Args)]
 pub struct GetElementArgs {
@@ -8,7 +15,8 @@ pub struct GetElementArgs {
 }
 
 pub fn get_element(args: &GetElementArgs) -> Result<()> {
-    rpc::call("get_element", json!({"id": args.id}))?.print()
+    let params = serde_json::to_value(GetElementParams { id: args.id })?;
+    rpc::call("get_element", params)?.print()
 }
 
 #[derive(Args)]
@@ -20,11 +28,264 @@ pub struct SetElementTagArgs {
 
 pub fn set_element_tag(args: &SetElementTagArgs) -> Result<()> {
     let value: Value = serde_json::from_str(&args.tag_value)?;
-    rpc::call(
-        "set_element_tag",
-        json!({"element_id": args.element_id, "tag_name": args.tag_name, "tag_value": value}),
-    )?
-    .print()
+    output::print(&apply_tag(args.element_id, &args.tag_name, Some(value))?.0)
+}
+
+/// Sets a tag, or removes it when the value is missing, and records the
+/// previous value in the local journal so the change can be undone. Returns
+/// the server result and the journal entry id.
+pub fn apply_tag(element_id: i64, tag_name: &str, value: Option<Value>) -> Result<(Value, i64)> {
+    let client = rpc::client()?;
+    let old_value = client
+        .get_element(&GetElementParams { id: element_id })?
+        .tags
+        .remove(tag_name);
+    let result = match &value {
+        Some(value) => client.set_element_tag(&SetElementTagParams {
+            element_id,
+            tag_name: tag_name.into(),
+            tag_value: value.clone(),
+        })?,
+        None => client.remove_element_tag(&RemoveElementTagParams {
+            element_id,
+            tag_name: tag_name.into(),
+        })?,
+    };
+    let entry = journal::record(
+        "element",
+        &element_id.to_string(),
+        tag_name,
+        old_value.as_ref(),
+        value.as_ref(),
+    )?;
+    Ok((result, entry))
+}
+
+#[derive(Args)]
+pub struct BulkTagArgs {
+    /// CSV (element_id,tag_name,tag_value) or NDJSON ({"element_id", "tag_name", "tag_value"}) file. Reads stdin when omitted or -
+    pub file: Option<String>,
+    /// Write rows that failed to this file, in the input format, so they can be re-run
+    #[arg(long)]
+    pub failures: Option<String>,
+    /// Number of changes sent in a single batch request
+    #[arg(long, default_value_t = 50)]
+    pub batch_size: usize,
+}
+
+struct TagRow {
+    line: usize,
+    raw: String,
+    element_id: i64,
+    tag_name: String,
+    tag_value: Value,
+}
+
+pub fn bulk_tag(args: &BulkTagArgs) -> Result<()> {
+    let input = common::read_file_or_stdin(args.file.as_deref())?;
+    let rows = parse_tag_rows(&input)?;
+    let mut failures = vec![];
+    let mut done = 0;
+    // A dry run prints every planned call at once instead of stopping after the first chunk
+    let batch_size = if rpc::is_dry_run() {
+        rows.len()
+    } else {
+        args.batch_size
+    };
+    for chunk in rows.chunks(batch_size.max(1)) {
+        // A dry run stops at the first call, so there is nothing to journal
+        let mut tags = if rpc::is_dry_run() {
+            HashMap::new()
+        } else {
+            current_tags(chunk)
+        };
+        let calls: Vec<(String, Value)> = chunk
+            .iter()
+            .map(|it| {
+                (
+                    "set_element_tag".to_string(),
+                    json!({"element_id": it.element_id, "tag_name": it.tag_name, "tag_value": it.tag_value}),
+                )
+            })
+            .collect();
+        let responses = match rpc::call_batch(&calls) {
+            Ok(responses) => responses,
+            Err(e) if e.is::<rpc::DryRun>() => return Err(e),
+            Err(e) => chunk
+                .iter()
+                .map(|_| rpc::RpcResponse {
+                    result: None,
+                    error: Some(json!({ "message": e.to_string() })),
+                })
+                .collect(),
+        };
+        // Rows are applied in order, so a later row for the same tag replaces
+        // the value set by an earlier one in the chunk
+        for (row, response) in chunk.iter().zip(responses) {
+            if let Some(error) = response.error {
+                let message = error["message"].as_str().unwrap_or_default().to_string();
+                eprintln!(
+                    "line {}: element {} failed: {message}",
+                    row.line, row.element_id
+                );
+                failures.push((row, message));
+                continue;
+            }
+            match tags.get_mut(&row.element_id) {
+                Some(tags) => {
+                    // A null value clears the tag, journal it as a removal
+                    let new_value = Some(&row.tag_value).filter(|it| !it.is_null());
+                    let old_value = match new_value {
+                        Some(value) => tags.insert(row.tag_name.clone(), value.clone()),
+                        None => tags.remove(&row.tag_name),
+                    }
+                    .filter(|it| !it.is_null());
+                    journal::record(
+                        "element",
+                        &row.element_id.to_string(),
+                        &row.tag_name,
+                        old_value.as_ref(),
+                        new_value,
+                    )?;
+                }
+                None => eprintln!(
+                    "line {}: previous value of element {} is unknown, the change is not journaled",
+                    row.line, row.element_id
+                ),
+            }
+        }
+        done += chunk.len();
+        eprintln!("[{done}/{}] processed", rows.len());
+    }
+    println!(
+        "Applied {} of {} tag changes, {} failed",
+        rows.len() - failures.len(),
+        rows.len(),
+        failures.len()
+    );
+    if let Some(path) = &args.failures {
+        let mut file = std::fs::File::create(path)?;
+        for (row, message) in &failures {
+            writeln!(file, "# line {}: {}", row.line, message.replace('\n', " "))?;
+            writeln!(file, "{}", row.raw)?;
+        }
+    }
+    if !failures.is_empty() {
+        Err(format!("{} tag changes failed", failures.len()))?;
+    }
+    Ok(())
+}
+
+/// Fetches the current tags of every element in a chunk, for the journal.
+/// Elements that couldn't be fetched are left out, their changes are still
+/// applied but can't be undone.
+fn current_tags(chunk: &[TagRow]) -> HashMap<i64, Map<String, Value>> {
+    let mut ids: Vec<i64> = chunk.iter().map(|it| it.element_id).collect();
+    ids.sort();
+    ids.dedup();
+    let calls: Vec<(String, Value)> = ids
+        .iter()
+        .map(|id| ("get_element".to_string(), json!({ "id": id })))
+        .collect();
+    let responses = match rpc::call_batch(&calls) {
+        Ok(responses) => responses,
+        Err(e) => {
+            eprintln!("Failed to fetch the current tags, this chunk is not journaled: {e}");
+            return HashMap::new();
+        }
+    };
+    ids.into_iter()
+        .zip(responses)
+        .filter_map(|(id, response)| match response.result {
+            Some(element) => Some((id, element["tags"].as_object().cloned().unwrap_or_default())),
+            None => {
+                eprintln!("Failed to fetch element {id}: {}", response.to_error());
+                None
+            }
+        })
+        .collect()
+}
+
+/// Parses and validates every row before anything is sent, so a typo on the
+/// last line doesn't leave the data half-updated.
+fn parse_tag_rows(input: &str) -> Result<Vec<TagRow>> {
+    let mut rows = vec![];
+    let mut errors = vec![];
+    for (i, raw) in input.lines().enumerate() {
+        let line = i + 1;
+        let trimmed = raw.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let parsed: std::result::Result<(String, String, String), String> =
+            if trimmed.starts_with('{') {
+                serde_json::from_str::<Value>(trimmed)
+                    .map_err(|e| e.to_string())
+                    .map(|it| {
+                        (
+                            match &it["element_id"] {
+                                Value::String(id) => id.clone(),
+                                id => id.to_string(),
+                            },
+                            it["tag_name"].as_str().unwrap_or_default().to_string(),
+                            // NDJSON rows carry the tag value as JSON already
+                            it.get("tag_value")
+                                .map(|it| it.to_string())
+                                .unwrap_or_default(),
+                        )
+                    })
+            } else {
+                csv::parse_line(trimmed).and_then(|fields| match fields.as_slice() {
+                    [id, name, value] => Ok((id.clone(), name.clone(), value.clone())),
+                    _ => Err(format!("expected 3 fields, got {}", fields.len())),
+                })
+            };
+        let (id, tag_name, tag_value) = match parsed {
+            Ok(it) => it,
+            Err(e) => {
+                errors.push(format!("line {line}: {e}"));
+                continue;
+            }
+        };
+        if rows.is_empty() && errors.is_empty() && id == "element_id" {
+            continue;
+        }
+        let element_id = match id.trim().parse::<i64>() {
+            Ok(id) => id,
+            Err(_) => {
+                errors.push(format!("line {line}: invalid element id {id:?}"));
+                continue;
+            }
+        };
+        if tag_name.is_empty() {
+            errors.push(format!("line {line}: tag_name is missing"));
+            continue;
+        }
+        let tag_value: Value = match serde_json::from_str(&tag_value) {
+            Ok(value) => value,
+            Err(e) => {
+                errors.push(format!(
+                    "line {line}: tag_value {tag_value:?} is not a valid JSON value ({e})"
+                ));
+                continue;
+            }
+        };
+        rows.push(TagRow {
+            line,
+            raw: raw.to_string(),
+            element_id,
+            tag_name,
+            tag_value,
+        });
+    }
+    if !errors.is_empty() {
+        Err(format!(
+            "{} invalid rows, nothing was sent:\n{}",
+            errors.len(),
+            errors.join("\n")
+        ))?;
+    }
+    Ok(rows)
 }
 
 #[derive(Args)]
@@ -34,11 +295,7 @@ pub struct RemoveElementTagArgs {
 }
 
 pub fn remove_element_tag(args: &RemoveElementTagArgs) -> Result<()> {
-    rpc::call(
-        "remove_element_tag",
-        json!({"element_id": args.element_id, "tag_name": args.tag_name}),
-    )?
-    .print()
+    output::print(&apply_tag(args.element_id, &args.tag_name, None)?.0)
 }
 
 pub fn get_boosted_elements() -> Result<()> {
@@ -63,14 +320,17 @@ pub fn paywall_get_boost_element_quote() -> Result<()> {
 pub struct PaywallBoostElementArgs {
     pub element_id: String,
     pub days: i64,
+    #[command(flatten)]
+    pub invoice: invoice::InvoiceArgs,
 }
 
 pub fn paywall_boost_element(args: &PaywallBoostElementArgs) -> Result<()> {
-    rpc::call(
+    let result = rpc::call(
         "paywall_boost_element",
         json!({"element_id": args.element_id, "days": args.days}),
     )?
-    .print()
+    .into_result()?;
+    invoice::print(&result, &args.invoice)
 }
 
 #[derive(Args)]
@@ -95,14 +355,17 @@ pub fn paywall_get_add_element_comment_quote() -> Result<()> {
 pub struct PaywallAddElementCommentArgs {
     pub element_id: String,
     pub comment: String,
+    #[command(flatten)]
+    pub invoice: invoice::InvoiceArgs,
 }
 
 pub fn paywall_add_element_comment(args: &PaywallAddElementCommentArgs) -> Result<()> {
-    rpc::call(
+    let result = rpc::call(
         "paywall_add_element_comment",
         json!({"element_id": args.element_id,"comment": args.comment}),
     )?
-    .print()
+    .into_result()?;
+    invoice::print(&result, &args.invoice)
 }
 
 #[derive(Args)]
@@ -1,9 +1,11 @@
 use crate::{
-    rpc::{self},
+    command::area,
+    rpc::{self, Print},
     Result,
 };
+use btcmap_cli::types::{GetEventParams, GetEventsParams};
 use clap::Args;
-use serde_json::{json, Map, Value};
+use serde_json::json;
 
 #[derive(Args)]
 pub struct CreateEventArgs {
@@ -47,10 +49,11 @@ pub struct GetEventsArgs {
 }
 
 pub fn get_events(args: &GetEventsArgs) -> Result<()> {
-    let mut params = Map::new();
-    params.insert("include_past".into(), Value::Bool(args.include_past));
-    params.insert("include_deleted".into(), Value::Bool(args.include_deleted));
-    rpc::call("get_events", Value::Object(params))?.print()
+    let params = serde_json::to_value(GetEventsParams {
+        include_past: args.include_past,
+        include_deleted: args.include_deleted,
+    })?;
+    rpc::call("get_events", params)?.print()
 }
 
 #[derive(Args)]
@@ -59,7 +62,8 @@ pub struct GetEventArgs {
 }
 
 pub fn get_event(args: &GetEventArgs) -> Result<()> {
-    rpc::call("get_event", json!({"id": args.id}))?.print()
+    let params = serde_json::to_value(GetEventParams { id: args.id })?;
+    rpc::call("get_event", params)?.print()
 }
 
 #[derive(Args)]
@@ -86,6 +90,9 @@ pub struct UpdateEventArgs {
 }
 
 pub fn update_event(args: &UpdateEventArgs) -> Result<()> {
+    if let (None, Some(lat), Some(lon)) = (args.area_id, args.lat, args.lon) {
+        suggest_area(lat, lon);
+    }
     let params = json!({
         "id": args.id,
         "area_id": args.area_id,
@@ -108,3 +115,24 @@ pub struct DeleteEventArgs {
 pub fn delete_event(args: &DeleteEventArgs) -> Result<()> {
     rpc::call("delete_event", json!({"id": args.id}))?.print()
 }
+
+/// Prints cached areas containing the new event location, so the right
+/// --area-id is one copy-paste away. Lookup errors never block the update.
+fn suggest_area(lat: f64, lon: f64) {
+    let Ok(matches) = area::cached_features().map(|it| area::containing_areas(&it, lat, lon))
+    else {
+        return;
+    };
+    if matches.is_empty() {
+        return;
+    }
+    eprintln!("Cached areas containing this location, pass one of them as --area-id:");
+    for it in matches {
+        eprintln!(
+            "  {} {} ({})",
+            it["id"],
+            it["alias"].as_str().unwrap_or_default(),
+            it["type"].as_str().unwrap_or("unknown type")
+        );
+    }
+}
@@ -0,0 +1,75 @@
+use crate::{
+    command::{area, element},
+    journal, output, rpc, Result,
+};
+use btcmap_cli::types::GetElementParams;
+use clap::Args;
+use serde_json::json;
+
+#[derive(Args)]
+pub struct ListArgs {
+    #[arg(long, default_value_t = 20)]
+    pub limit: i64,
+}
+
+pub fn list(args: &ListArgs) -> Result<()> {
+    let entries: Vec<_> = journal::entries(args.limit)?
+        .into_iter()
+        .map(|it| {
+            json!({
+                "id": it.id,
+                "created_at": it.created_at,
+                "target": it.target,
+                "target_id": it.target_id,
+                "tag": it.tag,
+                "old_value": it.old_value,
+                "new_value": it.new_value,
+                "undone_by": it.undone_by,
+            })
+        })
+        .collect();
+    output::print(&json!(entries))
+}
+
+#[derive(Args)]
+pub struct UndoArgs {
+    pub id: i64,
+    /// Undo even if the tag was changed again after this entry or the entry was already undone
+    #[arg(long)]
+    pub force: bool,
+}
+
+pub fn undo(args: &UndoArgs) -> Result<()> {
+    let entry = journal::get(args.id)?;
+    if let (Some(undone_by), false) = (entry.undone_by, args.force) {
+        Err(format!(
+            "entry {} was already undone by entry {undone_by}, pass --force to apply it again",
+            entry.id
+        ))?;
+    }
+    let current = match entry.target.as_str() {
+        "element" => rpc::client()?
+            .get_element(&GetElementParams {
+                id: entry.target_id.parse()?,
+            })?
+            .tags
+            .remove(&entry.tag),
+        _ => area::current_tag(&entry.target_id, &entry.tag)?.1,
+    };
+    if current != entry.new_value && !args.force {
+        Err(format!(
+            "{} {} tag {} has changed since entry {} (now {}), pass --force to overwrite it",
+            entry.target,
+            entry.target_id,
+            entry.tag,
+            entry.id,
+            current.map(|it| it.to_string()).unwrap_or("removed".into())
+        ))?;
+    }
+    let (result, undo) = match entry.target.as_str() {
+        "element" => element::apply_tag(entry.target_id.parse()?, &entry.tag, entry.old_value)?,
+        _ => area::apply_tag(&entry.target_id, &entry.tag, entry.old_value)?,
+    };
+    output::print(&result)?;
+    journal::mark_undone(entry.id, undo)
+}
@@ -0,0 +1,187 @@
+use crate::{bolt11, output, rpc, Result};
+use clap::Args;
+use qrcode::{render::unicode, QrCode};
+use serde_json::{json, Value};
+use std::{
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Options shared by every command that returns an invoice.
+#[derive(Args)]
+pub struct InvoiceArgs {
+    /// Decode the returned invoice and draw it as a QR code on stderr
+    #[arg(long)]
+    pub qr: bool,
+    /// Poll get_invoice until the invoice is paid or expired. Exits with 8 if it expires
+    #[arg(long)]
+    pub wait: bool,
+    /// Seconds between get_invoice calls while waiting
+    #[arg(long, default_value_t = 3.0, requires = "wait", value_parser = crate::positive_seconds)]
+    pub poll_interval: f64,
+}
+
+/// Prints the result of a paywall call that created an invoice and then
+/// handles --qr and --wait.
+pub fn print(result: &Value, args: &InvoiceArgs) -> Result<()> {
+    handle(result, find_invoice_id(result), args)
+}
+
+/// Same as print, for results that are the invoice itself, so their top-level
+/// id is the invoice id.
+pub fn print_invoice(invoice: &Value, args: &InvoiceArgs) -> Result<()> {
+    handle(invoice, invoice["id"].as_i64(), args)
+}
+
+fn handle(result: &Value, invoice_id: Option<i64>, args: &InvoiceArgs) -> Result<()> {
+    output::print(result)?;
+    if !args.qr && !args.wait {
+        return Ok(());
+    }
+    let payment_request = find_payment_request(result);
+    let decoded = payment_request.as_deref().and_then(decode_or_warn);
+    if args.qr {
+        let payment_request = payment_request
+            .as_deref()
+            .ok_or("the response contains no invoice")?;
+        if let Some(decoded) = &decoded {
+            eprintln!("{}", summary(decoded));
+        }
+        eprintln!("{}", qr(payment_request)?);
+    }
+    if args.wait {
+        let id = invoice_id.ok_or("the response contains no invoice id")?;
+        wait(id, decoded.map(|it| it.expires_at()), args.poll_interval)?;
+    }
+    Ok(())
+}
+
+#[derive(Args)]
+pub struct DecodeArgs {
+    /// BOLT11 payment request, with or without the lightning: prefix
+    pub invoice: String,
+    /// Also draw the invoice as a QR code on stderr
+    #[arg(long)]
+    pub qr: bool,
+}
+
+pub fn decode(args: &DecodeArgs) -> Result<()> {
+    let invoice = bolt11::decode(&args.invoice)?;
+    let mut res = invoice.to_json();
+    res["expired"] = json!(invoice.expires_at() < now());
+    output::print(&res)?;
+    if args.qr {
+        eprintln!("{}", qr(&args.invoice)?);
+    }
+    Ok(())
+}
+
+#[derive(Args)]
+pub struct WaitArgs {
+    pub id: i64,
+    /// Seconds between get_invoice calls
+    #[arg(long, default_value_t = 3.0, value_parser = crate::positive_seconds)]
+    pub poll_interval: f64,
+}
+
+pub fn wait_for(args: &WaitArgs) -> Result<()> {
+    wait(args.id, None, args.poll_interval)
+}
+
+/// Polls get_invoice until the status is final. Invoices that are still
+/// unpaid after their BOLT11 expiry count as expired, in case the server
+/// hasn't synced them yet. Without a known expiry, it's taken from the
+/// payment request of the first get_invoice result.
+fn wait(id: i64, expires_at: Option<u64>, poll_interval: f64) -> Result<()> {
+    let client = rpc::client()?;
+    let mut last_status = String::new();
+    let mut expires_at = expires_at;
+    let mut first = true;
+    loop {
+        let invoice = client
+            .call("get_invoice", json!({ "id": id }))?
+            .into_result()?;
+        if first && expires_at.is_none() {
+            expires_at = find_payment_request(&invoice)
+                .as_deref()
+                .and_then(decode_or_warn)
+                .map(|it| it.expires_at());
+        }
+        first = false;
+        let status = invoice["status"]
+            .as_str()
+            .unwrap_or_default()
+            .to_lowercase();
+        if status != last_status {
+            eprintln!("Invoice {id} is {status}");
+            last_status = status.clone();
+        }
+        match status.as_str() {
+            "paid" | "settled" => return Ok(()),
+            "expired" | "cancelled" | "canceled" => Err(rpc::InvoiceExpired(id))?,
+            _ if expires_at.is_some_and(|it| it < now()) => Err(rpc::InvoiceExpired(id))?,
+            _ => thread::sleep(Duration::from_secs_f64(poll_interval)),
+        }
+    }
+}
+
+/// The call that returned the invoice already succeeded, an invoice we can't
+/// decode only loses the summary and the local expiry check.
+fn decode_or_warn(payment_request: &str) -> Option<bolt11::Invoice> {
+    match bolt11::decode(payment_request) {
+        Ok(decoded) => Some(decoded),
+        Err(e) => {
+            eprintln!("Warning: failed to decode the invoice: {e}");
+            None
+        }
+    }
+}
+
+fn summary(invoice: &bolt11::Invoice) -> String {
+    let amount = match invoice.amount_msat {
+        Some(msat) => format!("{} sats", msat as f64 / 1000.0),
+        None => "any amount".into(),
+    };
+    let expires_in = invoice.expires_at().saturating_sub(now());
+    format!(
+        "{amount} on {}, expires in {}m {}s\n{}\npayment hash {}",
+        invoice.network,
+        expires_in / 60,
+        expires_in % 60,
+        invoice.description.as_deref().unwrap_or_default(),
+        invoice.payment_hash
+    )
+}
+
+fn qr(payment_request: &str) -> Result<String> {
+    // Uppercase fits the denser alphanumeric QR mode
+    let code = QrCode::new(payment_request.trim().to_uppercase())?;
+    // Inverted so the code reads as dark on light on a dark terminal background
+    Ok(code
+        .render::<unicode::Dense1x2>()
+        .dark_color(unicode::Dense1x2::Light)
+        .light_color(unicode::Dense1x2::Dark)
+        .build())
+}
+
+fn find_payment_request(result: &Value) -> Option<String> {
+    match result {
+        Value::String(s) if bolt11::is_invoice(s) => Some(s.clone()),
+        Value::Object(fields) => fields.values().find_map(find_payment_request),
+        _ => None,
+    }
+}
+
+/// No fallback to a top-level id, paywall responses use it for the element.
+fn find_invoice_id(result: &Value) -> Option<i64> {
+    result["invoice_id"]
+        .as_i64()
+        .or(result["invoice"]["id"].as_i64())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|it| it.as_secs())
+        .unwrap_or_default()
+}
@@ -1,5 +1,5 @@
 use crate::{
-    rpc::{self},
+    rpc::{self, Print},
     Result,
 };
 use clap::Args;
@@ -6,9 +6,12 @@ pub mod dashboard;
 pub mod electrum_server;
 pub mod element;
 pub mod event;
+pub mod history;
 pub mod import;
+pub mod invoice;
 pub mod matrix;
 pub mod report;
 pub mod setup;
+pub mod shell;
 pub mod user;
 pub mod wallet;
@@ -1,4 +1,7 @@
-use crate::{rpc, Result};
+use crate::{
+    rpc::{self, Print},
+    Result,
+};
 use clap::Args;
 use serde_json::json;
 
@@ -1,6 +1,6 @@
-use crate::{settings, verbosity, Result};
-use clap::Args;
-use colored_json::ToColoredJson;
+use crate::{command::shell, credentials, output, settings, verbosity, Result};
+use clap::{Args, Subcommand};
+use clap_complete::engine::ArgValueCandidates;
 use serde_json::json;
 
 #[derive(Args)]
@@ -9,24 +9,139 @@ pub struct SetServerArgs {
 }
 
 pub fn set_server(args: &SetServerArgs) -> Result<()> {
-    let url = match args.url.as_str() {
-        "prod" => "https://api.btcmap.org/rpc",
-        "dev" => "http://127.0.0.1:8000/rpc",
-        _ => &args.url,
-    };
+    let url = settings::server_url(&args.url);
     if verbosity() > 0 {
         println!("Old value: {}", settings::get_str("api_url")?);
         println!("New value: {}", url);
     }
-    settings::put_str("api_url", &url)?;
+    settings::put_str("api_url", url)?;
+    Ok(())
+}
+
+#[derive(Args)]
+pub struct StateArgs {
+    /// Print the API token in clear text. Unlocks the credential backend if needed
+    #[arg(long)]
+    pub show_secrets: bool,
+}
+
+pub fn state(args: &StateArgs) -> Result<()> {
+    let api_url = settings::api_url()?;
+    let backend = credentials::backend()?;
+    let profile = settings::active_profile()?;
+    let password = if args.show_secrets {
+        settings::api_key()?
+    } else {
+        match settings::api_key_override() {
+            Some(api_key) => settings::Resolved {
+                value: credentials::mask(&api_key.value),
+                source: api_key.source,
+            },
+            None => settings::Resolved {
+                value: match backend.name() {
+                    credentials::PLAINTEXT => credentials::mask(&backend.get(&profile)?),
+                    _ if backend.is_set(&profile)? => "<encrypted>".into(),
+                    _ => "".into(),
+                },
+                source: "profile",
+            },
+        }
+    };
+    let state = json!({
+        "profile": profile,
+        "server": api_url.value,
+        "server_source": api_url.source,
+        "password": password.value,
+        "password_source": password.source,
+        "credential_backend": backend.name(),
+    });
+    output::print(&state)
+}
+
+#[derive(Args)]
+pub struct ProfileArgs {
+    #[command(subcommand)]
+    pub command: ProfileCommand,
+}
+
+#[derive(Subcommand)]
+pub enum ProfileCommand {
+    /// Create a new profile. Sign in with --profile <name> to store its token
+    Add(AddProfileArgs),
+    /// List all profiles and mark the active one
+    List,
+    /// Make a profile active for all subsequent commands
+    Use(UseProfileArgs),
+    /// Remove a profile together with its token. The active profile can't be removed
+    Remove(RemoveProfileArgs),
+}
+
+#[derive(Args)]
+pub struct AddProfileArgs {
+    pub name: String,
+    /// JSON RPC API URL, prod and dev shortcuts are supported
+    #[arg(long, default_value = "prod")]
+    pub server: String,
+}
+
+#[derive(Args)]
+pub struct UseProfileArgs {
+    #[arg(add = ArgValueCandidates::new(shell::profile_candidates))]
+    pub name: String,
+}
+
+#[derive(Args)]
+pub struct RemoveProfileArgs {
+    #[arg(add = ArgValueCandidates::new(shell::profile_candidates))]
+    pub name: String,
+}
+
+pub fn profile(args: &ProfileArgs) -> Result<()> {
+    match &args.command {
+        ProfileCommand::Add(args) => {
+            settings::add_profile(&args.name, settings::server_url(&args.server))?;
+            println!("Profile {} has been added", args.name);
+        }
+        ProfileCommand::List => {
+            let active = settings::active_profile()?;
+            let mut profiles = vec![];
+            for profile in settings::profiles()? {
+                let backend = credentials::backend_by_name(&profile.credential_backend)?;
+                profiles.push(json!({
+                    "name": profile.name,
+                    "active": profile.name == active,
+                    "server": profile.api_url,
+                    "credential_backend": backend.name(),
+                    "signed_in": backend.is_set(&profile.name)?,
+                }));
+            }
+            output::print(&json!(profiles))?;
+        }
+        ProfileCommand::Use(args) => {
+            settings::use_profile(&args.name)?;
+            println!("Switched to profile {}", args.name);
+        }
+        ProfileCommand::Remove(args) => {
+            settings::remove_profile(&args.name)?;
+            println!("Profile {} has been removed", args.name);
+        }
+    }
     Ok(())
 }
 
 #[derive(Args)]
-pub struct StateArgs {}
+pub struct SetCredentialBackendArgs {
+    /// Either plaintext (settings database) or encrypted-file (passphrase protected, see BTCMAP_PASSPHRASE)
+    #[arg(value_parser = [credentials::PLAINTEXT, credentials::ENCRYPTED_FILE])]
+    pub backend: String,
+}
 
-pub fn state(_: &StateArgs) -> Result<()> {
-    let state = json!({ "server": settings::get_str("api_url")?, "password": settings::get_str("password")? });
-    println!("{}", serde_json::to_string(&state)?.to_colored_json_auto()?);
+pub fn set_credential_backend(args: &SetCredentialBackendArgs) -> Result<()> {
+    credentials::switch_backend(&args.backend)?;
+    println!(
+        "Profile {} now stores its token in {}",
+        settings::active_profile()?,
+        args.backend
+    );
     Ok(())
 }
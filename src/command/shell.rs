@@ -0,0 +1,66 @@
+use crate::{cache, settings, Result};
+use clap::{Args, Command};
+use clap_complete::{engine::CompletionCandidate, env::Shells};
+use std::io::Write;
+
+#[derive(Args)]
+pub struct CompletionsArgs {
+    /// Shell to print the completion script for
+    #[arg(value_parser = ["bash", "zsh", "fish", "elvish"])]
+    pub shell: String,
+}
+
+/// Prints a script that asks the binary for completions at runtime, so area
+/// aliases and profile names stay current without regenerating it.
+pub fn completions(args: &CompletionsArgs) -> Result<()> {
+    let shells = Shells::builtins();
+    let completer = shells
+        .completer(&args.shell)
+        .ok_or(format!("unsupported shell {}", args.shell))?;
+    let name = env!("CARGO_PKG_NAME");
+    let mut out = std::io::stdout().lock();
+    completer.write_registration("COMPLETE", name, name, name, &mut out)?;
+    out.flush()?;
+    Ok(())
+}
+
+#[derive(Args)]
+pub struct ManpageArgs {
+    /// Write one page per section and command to this directory instead of
+    /// printing the top-level page
+    #[arg(long)]
+    pub out_dir: Option<String>,
+}
+
+pub fn manpage(args: &ManpageArgs, cli: Command) -> Result<()> {
+    match &args.out_dir {
+        Some(dir) => {
+            std::fs::create_dir_all(dir)?;
+            clap_mangen::generate_to(cli, dir)?;
+            eprintln!("Wrote man pages to {dir}");
+        }
+        None => clap_mangen::Man::new(cli).render(&mut std::io::stdout().lock())?,
+    }
+    Ok(())
+}
+
+/// Aliases of the areas in the local cache. Completion must never fail, so
+/// a missing database just means no suggestions.
+pub fn area_candidates() -> Vec<CompletionCandidate> {
+    cache::area_aliases()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(alias, name)| {
+            let help = name.unwrap_or(alias.clone());
+            CompletionCandidate::new(alias).help(Some(help.into()))
+        })
+        .collect()
+}
+
+pub fn profile_candidates() -> Vec<CompletionCandidate> {
+    settings::profiles()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|profile| CompletionCandidate::new(profile.name).help(Some(profile.api_url.into())))
+        .collect()
+}
@@ -1,9 +1,16 @@
-use crate::{rpc, Result};
+use crate::{
+    cache, descriptor, output,
+    rpc::{self, Print},
+    xpub, Result,
+};
+use btcmap_cli::types::{GetWalletsParams, Wallet};
 use clap::Args;
-use serde_json::json;
+use serde_json::{json, Map, Value};
 
 pub fn list(args: &ListArgs) -> Result<()> {
-    let params = json!({ "include_deleted": args.include_deleted });
+    let params = serde_json::to_value(GetWalletsParams {
+        include_deleted: args.include_deleted,
+    })?;
     rpc::call("get_wallets", params)?.print()
 }
 
@@ -13,20 +20,220 @@ pub struct ListArgs {
     pub include_deleted: bool,
 }
 
+/// Balance fields of get_wallets rows, first match wins.
+const CONFIRMED_FIELDS: [&str; 4] = [
+    "confirmed_balance_sats",
+    "balance_sats",
+    "confirmed_sats",
+    "balance",
+];
+const UNCONFIRMED_FIELDS: [&str; 3] = [
+    "unconfirmed_balance_sats",
+    "unconfirmed_sats",
+    "unconfirmed_balance",
+];
+/// When the refresher last updated a balance, first match wins.
+const REFRESHED_FIELDS: [&str; 4] = [
+    "balance_updated_at",
+    "balance_refreshed_at",
+    "refreshed_at",
+    "updated_at",
+];
+const SATS_PER_BTC: i64 = 100_000_000;
+
+#[derive(Args)]
+pub struct SummaryArgs {
+    /// Flag wallets whose balance wasn't refreshed for this many minutes. The refresher runs every 5 minutes
+    #[arg(long, default_value_t = 30.0)]
+    pub stale_after: f64,
+    /// Record the totals in the local database, see wallet history
+    #[arg(long)]
+    pub snapshot: bool,
+}
+
+/// Totals the cached balances of all wallets that aren't soft-deleted.
+/// Stale wallets are still counted, with a warning on stderr.
+pub fn summary(args: &SummaryArgs) -> Result<()> {
+    let wallets: Vec<Wallet> = rpc::client()?
+        .get_wallets(&GetWalletsParams {
+            include_deleted: false,
+        })?
+        .into_iter()
+        .filter(|it| it.extra.get("deleted_at").is_none_or(Value::is_null))
+        .collect();
+    let mut confirmed = 0;
+    let mut unconfirmed = 0;
+    let mut stale = vec![];
+    for wallet in &wallets {
+        let wallet_confirmed = sats(&wallet.extra, &CONFIRMED_FIELDS);
+        confirmed += wallet_confirmed.unwrap_or(0);
+        unconfirmed += sats(&wallet.extra, &UNCONFIRMED_FIELDS).unwrap_or(0);
+        if let Some(reason) = stale_reason(wallet, wallet_confirmed, args.stale_after)? {
+            eprintln!(
+                "Warning: wallet {} ({}) {reason}",
+                wallet.id,
+                wallet.name.as_deref().unwrap_or_default()
+            );
+            stale.push(wallet.id);
+        }
+    }
+    let mut res = json!({
+        "wallets": wallets.len(),
+        "confirmed_sats": confirmed,
+        "unconfirmed_sats": unconfirmed,
+        "total_sats": confirmed + unconfirmed,
+        "confirmed_btc": btc(confirmed),
+        "unconfirmed_btc": btc(unconfirmed),
+        "total_btc": btc(confirmed + unconfirmed),
+        "stale_wallet_ids": stale,
+    });
+    if args.snapshot && rpc::is_dry_run() {
+        eprintln!("Dry run, the snapshot was not saved");
+    } else if args.snapshot {
+        let snapshot = cache::put_wallet_snapshot(wallets.len() as i64, confirmed, unconfirmed)?;
+        res["snapshot"] = json!(snapshot.id);
+        res["taken_at"] = json!(snapshot.taken_at);
+    }
+    output::print(&res)
+}
+
+#[derive(Args)]
+pub struct HistoryArgs {
+    /// Number of most recent snapshots to show
+    #[arg(long, default_value_t = 30)]
+    pub limit: usize,
+    /// Draw the total balance as a bar chart instead of printing rows
+    #[arg(long)]
+    pub graph: bool,
+    /// Width of the longest bar in the chart
+    #[arg(long, default_value_t = 50, requires = "graph")]
+    pub width: usize,
+}
+
+/// Lists snapshots recorded by wallet summary --snapshot with the change
+/// in total balance since the previous one.
+pub fn history(args: &HistoryArgs) -> Result<()> {
+    let snapshots = cache::wallet_snapshots()?;
+    if snapshots.is_empty() {
+        Err("no snapshots found, run btcmap-cli wallet summary --snapshot first")?;
+    }
+    let skip = snapshots.len().saturating_sub(args.limit);
+    if args.graph {
+        let snapshots = &snapshots[skip..];
+        let max = snapshots
+            .iter()
+            .map(|it| it.confirmed_sats + it.unconfirmed_sats)
+            .max()
+            .unwrap_or(0);
+        for snapshot in snapshots {
+            let total = snapshot.confirmed_sats + snapshot.unconfirmed_sats;
+            println!(
+                "{}  {:<width$}  {} BTC",
+                snapshot.taken_at,
+                bar(total, max, args.width),
+                btc(total),
+                width = args.width
+            );
+        }
+        return Ok(());
+    }
+    let mut res = vec![];
+    for (i, snapshot) in snapshots.iter().enumerate().skip(skip) {
+        let total = snapshot.confirmed_sats + snapshot.unconfirmed_sats;
+        let change = match i {
+            0 => 0,
+            _ => total - snapshots[i - 1].confirmed_sats - snapshots[i - 1].unconfirmed_sats,
+        };
+        res.push(json!({
+            "snapshot": snapshot.id,
+            "taken_at": snapshot.taken_at,
+            "wallets": snapshot.wallets,
+            "confirmed_sats": snapshot.confirmed_sats,
+            "unconfirmed_sats": snapshot.unconfirmed_sats,
+            "total_btc": btc(total),
+            "change_sats": change,
+        }));
+    }
+    output::print(&json!(res))
+}
+
+fn sats(fields: &Map<String, Value>, names: &[&str]) -> Option<i64> {
+    names
+        .iter()
+        .filter_map(|it| fields.get(*it))
+        .find(|it| !it.is_null())
+        .and_then(|it| it.as_i64().or(it.as_str().and_then(|it| it.parse().ok())))
+}
+
+fn stale_reason(
+    wallet: &Wallet,
+    confirmed: Option<i64>,
+    stale_after: f64,
+) -> Result<Option<String>> {
+    if confirmed.is_none() {
+        return Ok(Some("has no cached balance".into()));
+    }
+    let refreshed_at = REFRESHED_FIELDS
+        .iter()
+        .find_map(|it| wallet.extra.get(*it).and_then(Value::as_str));
+    let Some(refreshed_at) = refreshed_at else {
+        return Ok(None);
+    };
+    Ok(match cache::minutes_since(refreshed_at)? {
+        Some(minutes) if minutes > stale_after => Some(format!(
+            "balance was last refreshed {minutes:.0} minutes ago"
+        )),
+        Some(_) => None,
+        None => Some(format!("has an unreadable refresh time {refreshed_at}")),
+    })
+}
+
+/// Exact BTC amount, floats would print 1000 sats as 1e-5.
+fn btc(sats: i64) -> String {
+    let sign = if sats < 0 { "-" } else { "" };
+    let sats = sats.unsigned_abs();
+    let per_btc = SATS_PER_BTC as u64;
+    format!("{sign}{}.{:08}", sats / per_btc, sats % per_btc)
+}
+
+/// Horizontal bar scaled to max, in eighths of a character.
+fn bar(value: i64, max: i64, width: usize) -> String {
+    const PARTIAL: [&str; 8] = ["", "▏", "▎", "▍", "▌", "▋", "▊", "▉"];
+    if max <= 0 || value <= 0 {
+        return "".into();
+    }
+    let eighths = (value as f64 / max as f64 * width as f64 * 8.0).round() as usize;
+    format!("{}{}", "█".repeat(eighths / 8), PARTIAL[eighths % 8])
+}
+
 #[derive(Args)]
 pub struct AddArgs {
     #[arg(long)]
     pub name: String,
+    #[arg(long, required_unless_present = "descriptor")]
+    pub xpub: Option<String>,
+    /// Output descriptor, for multisig and taproot wallets: wpkh, sh(wpkh), tr or wsh(multi)
+    #[arg(long, conflicts_with = "xpub")]
+    pub descriptor: Option<String>,
+    /// Send the key or descriptor without checking it locally
     #[arg(long)]
-    pub xpub: String,
+    pub skip_validation: bool,
 }
 
 pub fn add(args: &AddArgs) -> Result<()> {
-    let params = json!({
-        "name": args.name,
-        "xpub": args.xpub,
-    });
-    rpc::call("add_wallet", params)?.print()
+    let mut params = json!({ "name": args.name });
+    if let Some(xpub) = &args.xpub {
+        if !args.skip_validation {
+            check_xpub(xpub)?;
+        }
+        params["xpub"] = json!(xpub);
+    }
+    match &args.descriptor {
+        Some(descriptor) => {
+            call_with_descriptor("add_wallet", params, descriptor, args.skip_validation)
+        }
+        None => rpc::call("add_wallet", params)?.print(),
+    }
 }
 
 #[derive(Args)]
@@ -36,9 +243,15 @@ pub struct UpdateArgs {
     pub name: Option<String>,
     #[arg(long)]
     pub xpub: Option<String>,
+    /// Output descriptor, for multisig and taproot wallets: wpkh, sh(wpkh), tr or wsh(multi)
+    #[arg(long, conflicts_with = "xpub")]
+    pub descriptor: Option<String>,
     /// Soft-delete the wallet by passing an RFC 3339 timestamp; pass the empty string to clear the soft-delete.
     #[arg(long)]
     pub deleted_at: Option<String>,
+    /// Send the key or descriptor without checking it locally
+    #[arg(long)]
+    pub skip_validation: bool,
 }
 
 pub fn update(args: &UpdateArgs) -> Result<()> {
@@ -47,6 +260,9 @@ pub fn update(args: &UpdateArgs) -> Result<()> {
         params["name"] = json!(name);
     }
     if let Some(xpub) = &args.xpub {
+        if !args.skip_validation {
+            check_xpub(xpub)?;
+        }
         params["xpub"] = json!(xpub);
     }
     if let Some(deleted_at) = &args.deleted_at {
@@ -56,7 +272,52 @@ pub fn update(args: &UpdateArgs) -> Result<()> {
             params["deleted_at"] = json!(deleted_at);
         }
     }
-    rpc::call("update_wallet", params)?.print()
+    match &args.descriptor {
+        Some(descriptor) => {
+            call_with_descriptor("update_wallet", params, descriptor, args.skip_validation)
+        }
+        None => rpc::call("update_wallet", params)?.print(),
+    }
+}
+
+/// Sends params with a descriptor. Servers that predate descriptors reject
+/// the unknown field, in that case single key descriptors are sent again as
+/// an xpub.
+fn call_with_descriptor(
+    method: &str,
+    mut params: Value,
+    descriptor: &str,
+    skip_validation: bool,
+) -> Result<()> {
+    let parsed = if skip_validation {
+        None
+    } else {
+        Some(check_descriptor(descriptor)?)
+    };
+    let mut with_descriptor = params.clone();
+    with_descriptor["descriptor"] = json!(parsed.as_ref().map_or(descriptor, |it| &it.text));
+    let response = rpc::call(method, with_descriptor)?;
+    let error = match response.error {
+        Some(_) => response.to_error(),
+        None => return response.print(),
+    };
+    if error.code != -32602 && !error.message.to_lowercase().contains("descriptor") {
+        return Err(error.into());
+    }
+    let xpub = match &parsed {
+        Some(parsed) => parsed.as_xpub()?,
+        None => None,
+    };
+    match xpub {
+        Some(xpub) => {
+            eprintln!("The server doesn't accept descriptors ({error}), sending the key as {xpub} instead");
+            params["xpub"] = json!(xpub);
+            rpc::call(method, params)?.print()
+        }
+        None => Err(format!(
+            "the server doesn't accept descriptors ({error}) and this one can't be sent as an xpub"
+        ))?,
+    }
 }
 
 #[derive(Args)]
@@ -68,3 +329,82 @@ pub fn remove(args: &RemoveArgs) -> Result<()> {
     let params = json!({ "id": args.id });
     rpc::call("remove_wallet", params)?.print()
 }
+
+#[derive(Args)]
+pub struct DeriveArgs {
+    /// Extended public key (xpub, ypub, zpub, tpub, upub or vpub) or output descriptor
+    pub key: String,
+    /// Number of receive and change addresses
+    #[arg(long, default_value_t = 5)]
+    pub count: u32,
+    /// Index of the first address
+    #[arg(long, default_value_t = 0)]
+    pub start: u32,
+}
+
+/// Prints the first receive and change addresses of a key or descriptor, to
+/// compare with the wallet app before registering it.
+pub fn derive(args: &DeriveArgs) -> Result<()> {
+    let indexes = args.start..args.start.saturating_add(args.count);
+    let mut rows = vec![];
+    if args.key.contains('(') {
+        let descriptor = check_descriptor(&args.key)?;
+        let chains: &[(u32, &str)] = if descriptor.has_change() {
+            &[(0, "receive"), (1, "change")]
+        } else {
+            &[(0, "receive")]
+        };
+        for (chain, name) in chains {
+            for index in indexes.clone() {
+                rows.push(json!({
+                    "chain": name,
+                    "index": index,
+                    "address": descriptor.address(*chain, index)?.to_string(),
+                }));
+            }
+        }
+    } else {
+        let key = check_xpub(&args.key)?;
+        eprintln!(
+            "{}, {} {}, depth {}",
+            key.prefix,
+            key.network,
+            key.kind.name(),
+            key.xpub.depth
+        );
+        for (chain, name) in [(0, "receive"), (1, "change")] {
+            for index in indexes.clone() {
+                rows.push(json!({
+                    "chain": name,
+                    "path": format!("{chain}/{index}"),
+                    "address": key.address(chain, index)?.to_string(),
+                }));
+            }
+        }
+    }
+    output::print(&Value::Array(rows))
+}
+
+/// Parses a key and prints its warnings to stderr.
+fn check_xpub(xpub: &str) -> Result<xpub::ExtendedKey> {
+    let key = xpub::parse(xpub).map_err(|e| format!("invalid xpub: {e}"))?;
+    for warning in key.warnings() {
+        eprintln!("Warning: {warning}");
+    }
+    Ok(key)
+}
+
+/// Parses a descriptor and prints what it describes to stderr.
+fn check_descriptor(descriptor: &str) -> Result<descriptor::Descriptor> {
+    let parsed = descriptor::parse(descriptor).map_err(|e| format!("invalid descriptor: {e}"))?;
+    if parsed.checksum_added {
+        eprintln!("Descriptor has no checksum, using {}", parsed.text);
+    }
+    eprintln!("{}, {}", parsed.name(), parsed.network);
+    for key in &parsed.keys {
+        for warning in key.warnings() {
+            eprintln!("Warning: {warning}");
+        }
+    }
+    Ok(parsed)
+}
@@ -0,0 +1,238 @@
+use crate::{prompt, settings, Result};
+use argon2::Argon2;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, KeyInit, OsRng, Payload},
+    AeadCore, ChaCha20Poly1305, Key, Nonce,
+};
+use serde_json::{json, Value};
+use std::{
+    env,
+    fs::{self, File},
+    io::Write,
+    path::PathBuf,
+    sync::Mutex,
+};
+
+pub const PLAINTEXT: &str = "plaintext";
+pub const ENCRYPTED_FILE: &str = "encrypted-file";
+
+/// Where the API token of a profile is kept. Every profile picks its own
+/// backend, plaintext is used until setup set-credential-backend is called.
+pub trait Backend {
+    fn name(&self) -> &'static str;
+    /// Whether a token is stored, without unlocking the backend.
+    fn is_set(&self, profile: &str) -> Result<bool>;
+    fn get(&self, profile: &str) -> Result<String>;
+    /// Stores a token, an empty string removes it.
+    fn set(&self, profile: &str, token: &str) -> Result<()>;
+}
+
+/// Token stored as is in the settings database.
+pub struct Plaintext;
+
+impl Backend for Plaintext {
+    fn name(&self) -> &'static str {
+        PLAINTEXT
+    }
+
+    fn is_set(&self, profile: &str) -> Result<bool> {
+        Ok(!self.get(profile)?.is_empty())
+    }
+
+    fn get(&self, profile: &str) -> Result<String> {
+        settings::get_profile_str(profile, "password")
+    }
+
+    fn set(&self, profile: &str, token: &str) -> Result<()> {
+        settings::put_profile_str(profile, "password", token)
+    }
+}
+
+/// Tokens encrypted with ChaCha20-Poly1305 under a key derived from a
+/// passphrase with Argon2id. The passphrase is taken from BTCMAP_PASSPHRASE or
+/// prompted for on a terminal, so it works on headless machines without a
+/// keyring daemon.
+pub struct EncryptedFile {
+    pub path: PathBuf,
+}
+
+static PASSPHRASE: Mutex<Option<String>> = Mutex::new(None);
+
+impl EncryptedFile {
+    fn read(&self) -> Result<Value> {
+        if !self.path.exists() {
+            let mut salt = [0u8; 16];
+            OsRng.fill_bytes(&mut salt);
+            return Ok(json!({"kdf": "argon2id", "salt": STANDARD.encode(salt), "entries": {}}));
+        }
+        Ok(serde_json::from_str(&fs::read_to_string(&self.path)?)?)
+    }
+
+    fn write(&self, file: &Value) -> Result<()> {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut out: File = options.open(&self.path)?;
+        out.write_all(serde_json::to_string_pretty(file)?.as_bytes())?;
+        Ok(())
+    }
+
+    fn cipher(&self, file: &Value, confirm: bool) -> Result<ChaCha20Poly1305> {
+        let salt = STANDARD.decode(file["salt"].as_str().ok_or("credential file has no salt")?)?;
+        let passphrase = passphrase(confirm)?;
+        let mut key = [0u8; 32];
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|e| format!("failed to derive key: {e}"))?;
+        Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+    }
+}
+
+impl Backend for EncryptedFile {
+    fn name(&self) -> &'static str {
+        ENCRYPTED_FILE
+    }
+
+    fn is_set(&self, profile: &str) -> Result<bool> {
+        Ok(self.read()?["entries"].get(profile).is_some())
+    }
+
+    fn get(&self, profile: &str) -> Result<String> {
+        let file = self.read()?;
+        let entry = &file["entries"][profile];
+        if entry.is_null() {
+            return Ok("".into());
+        }
+        let token = decrypt(&self.cipher(&file, false)?, profile, entry)?
+            .ok_or("failed to decrypt the credential file, wrong passphrase?")?;
+        Ok(String::from_utf8(token)?)
+    }
+
+    fn set(&self, profile: &str, token: &str) -> Result<()> {
+        let mut file = self.read()?;
+        let entries = file["entries"]
+            .as_object()
+            .ok_or("credential file is corrupted")?;
+        if token.is_empty() {
+            file["entries"]
+                .as_object_mut()
+                .ok_or("credential file is corrupted")?
+                .remove(profile);
+        } else {
+            let cipher = self.cipher(&file, entries.is_empty())?;
+            // All entries must share one passphrase, or get fails for some
+            // profiles with no clear reason
+            if let Some((existing, entry)) = entries.iter().next() {
+                decrypt(&cipher, existing, entry)?.ok_or(
+                    "the passphrase doesn't match the one the credential file is encrypted with",
+                )?;
+            }
+            let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+            let ciphertext = cipher
+                .encrypt(
+                    &nonce,
+                    Payload {
+                        msg: token.as_bytes(),
+                        aad: profile.as_bytes(),
+                    },
+                )
+                .map_err(|_| "failed to encrypt the token")?;
+            file["entries"][profile] = json!({
+                "nonce": STANDARD.encode(nonce),
+                "ciphertext": STANDARD.encode(ciphertext),
+            });
+        }
+        self.write(&file)
+    }
+}
+
+/// Decrypted entry, None when the passphrase is wrong.
+fn decrypt(cipher: &ChaCha20Poly1305, profile: &str, entry: &Value) -> Result<Option<Vec<u8>>> {
+    let corrupted = || format!("credentials file entry for {profile} is corrupted");
+    let nonce = STANDARD
+        .decode(entry["nonce"].as_str().unwrap_or_default())
+        .map_err(|_| corrupted())?;
+    let ciphertext = STANDARD
+        .decode(entry["ciphertext"].as_str().unwrap_or_default())
+        .map_err(|_| corrupted())?;
+    // from_slice panics on any other length
+    if nonce.len() != Nonce::default().len() {
+        Err(corrupted())?;
+    }
+    Ok(cipher
+        .decrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: &ciphertext,
+                aad: profile.as_bytes(),
+            },
+        )
+        .ok())
+}
+
+pub fn backend_by_name(name: &str) -> Result<Box<dyn Backend>> {
+    match name {
+        "" | PLAINTEXT => Ok(Box::new(Plaintext)),
+        ENCRYPTED_FILE => Ok(Box::new(EncryptedFile {
+            path: settings::data_dir()?.join("credentials.json"),
+        })),
+        _ => Err(format!("unknown credential backend {name}"))?,
+    }
+}
+
+/// Backend configured for the active profile.
+pub fn backend() -> Result<Box<dyn Backend>> {
+    backend_by_name(&settings::get_str("credential_backend")?)
+}
+
+pub fn get_token() -> Result<String> {
+    backend()?.get(&settings::active_profile()?)
+}
+
+pub fn set_token(token: &str) -> Result<()> {
+    backend()?.set(&settings::active_profile()?, token)
+}
+
+/// Moves the token of the active profile to another backend.
+pub fn switch_backend(name: &str) -> Result<()> {
+    let profile = settings::active_profile()?;
+    let old = backend()?;
+    let new = backend_by_name(name)?;
+    if old.name() == new.name() {
+        return Ok(());
+    }
+    let token = old.get(&profile)?;
+    new.set(&profile, &token)?;
+    settings::put_str("credential_backend", new.name())?;
+    old.set(&profile, "")?;
+    Ok(())
+}
+
+/// Hides all but the last 4 characters of a secret.
+pub fn mask(secret: &str) -> String {
+    let chars: Vec<char> = secret.chars().collect();
+    if chars.len() <= 8 {
+        return "*".repeat(chars.len());
+    }
+    let tail: String = chars[chars.len() - 4..].iter().collect();
+    format!("{}{tail}", "*".repeat(chars.len() - 4))
+}
+
+fn passphrase(confirm: bool) -> Result<String> {
+    let mut cached = PASSPHRASE.lock().map_err(|_| "passphrase lock poisoned")?;
+    if let Some(passphrase) = cached.as_ref() {
+        return Ok(passphrase.clone());
+    }
+    let passphrase = match env::var("BTCMAP_PASSPHRASE") {
+        Ok(passphrase) => passphrase,
+        Err(_) => prompt::secret("Credential file passphrase: ", confirm)
+            .map_err(|e| format!("{e}, set BTCMAP_PASSPHRASE instead"))?,
+    };
+    if passphrase.is_empty() {
+        Err("passphrase can't be empty")?;
+    }
+    *cached = Some(passphrase.clone());
+    Ok(passphrase)
+}
@@ -0,0 +1,45 @@
+/// Quotes a field if it contains a separator, a quote or a line break.
+pub fn field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.into()
+    }
+}
+
+/// Splits a single CSV line into fields. Quoted fields may contain commas and
+/// doubled quotes, line breaks inside quotes are not supported.
+pub fn parse_line(line: &str) -> Result<Vec<String>, String> {
+    let mut fields = vec![];
+    let mut current = String::new();
+    let mut chars = line.chars().peekable();
+    let mut quoted = false;
+    let mut was_quoted = false;
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                current.push('"');
+                chars.next();
+            }
+            (true, '"') => quoted = false,
+            (true, c) => current.push(c),
+            (false, '"') if current.is_empty() && !was_quoted => {
+                quoted = true;
+                was_quoted = true;
+            }
+            (false, ',') => {
+                fields.push(std::mem::take(&mut current));
+                was_quoted = false;
+            }
+            (false, c) if was_quoted => {
+                return Err(format!("unexpected character {c:?} after a quoted field"))
+            }
+            (false, c) => current.push(c),
+        }
+    }
+    if quoted {
+        return Err("unterminated quoted field".into());
+    }
+    fields.push(current);
+    Ok(fields)
+}
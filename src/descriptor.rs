@@ -0,0 +1,410 @@
+use crate::{
+    xpub::{self, ExtendedKey, ScriptKind},
+    Result,
+};
+use bitcoin::{
+    bip32::{ChildNumber, Xpub},
+    opcodes::all::OP_CHECKMULTISIG,
+    script::Builder,
+    secp256k1::{Secp256k1, XOnlyPublicKey},
+    Address, Network, PublicKey,
+};
+
+const INPUT_CHARSET: &str =
+    "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const CHECKSUM_CHARSET: &str = "qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+/// Consensus limit on keys in a P2WSH multisig
+const MAX_MULTISIG_KEYS: usize = 20;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Wpkh,
+    ShWpkh,
+    Tr,
+    WshMulti { threshold: usize, sorted: bool },
+}
+
+/// Step of the derivation path written after a key.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Step {
+    Normal(u32),
+    /// <receive;change>
+    Chains(u32, u32),
+    /// The final *, replaced by the address index
+    Wildcard,
+}
+
+pub struct Key {
+    pub origin: Option<String>,
+    pub key: ExtendedKey,
+    path: Vec<Step>,
+}
+
+impl Key {
+    /// Checks the depth against the key origin when there is one, which
+    /// also covers multisig keys below the usual account level.
+    pub fn warnings(&self) -> Vec<String> {
+        let Some(origin) = &self.origin else {
+            return self.key.warnings();
+        };
+        let steps = origin.split('/').count() - 1;
+        if steps == self.key.xpub.depth as usize {
+            return vec![];
+        }
+        vec![format!(
+            "key origin [{origin}] has {steps} steps but the key is at depth {}",
+            self.key.xpub.depth
+        )]
+    }
+
+    fn derive(&self, chain: u32, index: u32) -> Result<Xpub> {
+        let path = self
+            .path
+            .iter()
+            .map(|step| match *step {
+                Step::Normal(n) => ChildNumber::from_normal_idx(n),
+                Step::Chains(receive, change) => {
+                    ChildNumber::from_normal_idx(if chain == 0 { receive } else { change })
+                }
+                Step::Wildcard => ChildNumber::from_normal_idx(index),
+            })
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(self
+            .key
+            .xpub
+            .derive_pub(&Secp256k1::verification_only(), &path)?)
+    }
+}
+
+/// A parsed output descriptor. Only ranged descriptors built from extended
+/// public keys are supported, which is what wallets export for receiving.
+pub struct Descriptor {
+    pub kind: Kind,
+    pub keys: Vec<Key>,
+    pub network: Network,
+    /// Descriptor with its checksum appended
+    pub text: String,
+    /// Whether the input came without a checksum and one was computed
+    pub checksum_added: bool,
+}
+
+impl Descriptor {
+    pub fn name(&self) -> String {
+        match self.kind {
+            Kind::Wpkh => "wpkh".into(),
+            Kind::ShWpkh => "sh(wpkh)".into(),
+            Kind::Tr => "tr".into(),
+            Kind::WshMulti { threshold, sorted } => {
+                let multi = if sorted { "sortedmulti" } else { "multi" };
+                format!("{threshold}-of-{} wsh({multi})", self.keys.len())
+            }
+        }
+    }
+
+    /// Whether the keys use <0;1> paths, so there is a change chain.
+    pub fn has_change(&self) -> bool {
+        self.keys
+            .iter()
+            .all(|key| key.path.iter().any(|it| matches!(it, Step::Chains(..))))
+    }
+
+    /// Address at index, on the receive chain (0) or the change chain (1).
+    pub fn address(&self, chain: u32, index: u32) -> Result<Address> {
+        let keys = self
+            .keys
+            .iter()
+            .map(|key| key.derive(chain, index))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(match self.kind {
+            Kind::Wpkh => Address::p2wpkh(&keys[0].to_pub(), self.network),
+            Kind::ShWpkh => Address::p2shwpkh(&keys[0].to_pub(), self.network),
+            Kind::Tr => Address::p2tr(
+                &Secp256k1::verification_only(),
+                XOnlyPublicKey::from(keys[0].public_key),
+                None,
+                self.network,
+            ),
+            Kind::WshMulti { threshold, sorted } => {
+                let mut keys: Vec<PublicKey> = keys
+                    .iter()
+                    .map(|it| PublicKey::new(it.public_key))
+                    .collect();
+                if sorted {
+                    keys.sort_by_key(|it| it.to_bytes());
+                }
+                let mut script = Builder::new().push_int(threshold as i64);
+                for key in &keys {
+                    script = script.push_key(key);
+                }
+                let script = script
+                    .push_int(keys.len() as i64)
+                    .push_opcode(OP_CHECKMULTISIG)
+                    .into_script();
+                Address::p2wsh(&script, self.network)
+            }
+        })
+    }
+
+    /// The same wallet as a single SLIP-132 key, for servers that don't
+    /// accept descriptors. None when an xpub can't express it.
+    pub fn as_xpub(&self) -> Result<Option<String>> {
+        let kind = match self.kind {
+            Kind::Wpkh => ScriptKind::P2wpkh,
+            Kind::ShWpkh => ScriptKind::P2shP2wpkh,
+            _ => return Ok(None),
+        };
+        // xpub wallets are scanned on 0/* and 1/*
+        let key = &self.keys[0];
+        if key.path != [Step::Chains(0, 1), Step::Wildcard]
+            && key.path != [Step::Normal(0), Step::Wildcard]
+        {
+            return Ok(None);
+        }
+        Ok(Some(xpub::encode(&key.key.xpub, self.network, kind)?))
+    }
+}
+
+/// Validates the checksum, if there is one, and parses the descriptor.
+pub fn parse(descriptor: &str) -> Result<Descriptor> {
+    let descriptor = descriptor.trim();
+    let (body, checksum) = match descriptor.split_once('#') {
+        Some((body, checksum)) => (body, Some(checksum)),
+        None => (descriptor, None),
+    };
+    let expected = checksum_of(body)?;
+    if let Some(checksum) = checksum {
+        if checksum != expected {
+            Err(format!(
+                "descriptor checksum is {checksum}, expected {expected}"
+            ))?;
+        }
+    }
+    if body.contains(char::is_whitespace) {
+        Err("descriptor contains whitespace")?;
+    }
+    let (kind, keys) = if let Some(key) = unwrap(body, "wpkh") {
+        (Kind::Wpkh, vec![parse_key(key)?])
+    } else if let Some(key) = unwrap(body, "sh").and_then(|it| unwrap(it, "wpkh")) {
+        (Kind::ShWpkh, vec![parse_key(key)?])
+    } else if let Some(key) = unwrap(body, "tr") {
+        if key.contains(',') {
+            Err("tr descriptors with script paths are not supported")?;
+        }
+        (Kind::Tr, vec![parse_key(key)?])
+    } else if let Some(inner) = unwrap(body, "wsh") {
+        let (args, sorted) = match (unwrap(inner, "multi"), unwrap(inner, "sortedmulti")) {
+            (Some(args), _) => (args, false),
+            (_, Some(args)) => (args, true),
+            _ => Err("only multi and sortedmulti are supported inside wsh")?,
+        };
+        let mut args = args.split(',');
+        let threshold: usize = args
+            .next()
+            .unwrap_or_default()
+            .parse()
+            .map_err(|_| "multisig threshold is not a number")?;
+        let keys = args.map(parse_key).collect::<Result<Vec<_>>>()?;
+        if keys.len() > MAX_MULTISIG_KEYS {
+            Err(format!(
+                "wsh multisig takes at most {MAX_MULTISIG_KEYS} keys, got {}",
+                keys.len()
+            ))?;
+        }
+        if threshold == 0 || threshold > keys.len() {
+            Err(format!(
+                "multisig threshold must be between 1 and {}, got {threshold}",
+                keys.len()
+            ))?;
+        }
+        (Kind::WshMulti { threshold, sorted }, keys)
+    } else {
+        let name = body.split('(').next().unwrap_or_default();
+        Err(format!(
+            "unsupported descriptor {name}(...), expected wpkh, sh(wpkh), tr or wsh(multi)"
+        ))?
+    };
+    let network = keys[0].key.network;
+    if keys.iter().any(|it| it.key.network != network) {
+        Err("descriptor mixes mainnet and testnet keys")?;
+    }
+    Ok(Descriptor {
+        kind,
+        keys,
+        network,
+        text: format!("{body}#{expected}"),
+        checksum_added: checksum.is_none(),
+    })
+}
+
+/// Inner part of name(...), if s has that shape.
+fn unwrap<'a>(s: &'a str, name: &str) -> Option<&'a str> {
+    s.strip_prefix(name)?.strip_prefix('(')?.strip_suffix(')')
+}
+
+/// [fingerprint/origin/path]xpub/path/*
+fn parse_key(s: &str) -> Result<Key> {
+    let (origin, rest) = match s.strip_prefix('[') {
+        Some(s) => {
+            let (origin, rest) = s.split_once(']').ok_or("key origin is missing ]")?;
+            let mut parts = origin.split('/');
+            let fingerprint = parts.next().unwrap_or_default();
+            if fingerprint.len() != 8 || !fingerprint.chars().all(|it| it.is_ascii_hexdigit()) {
+                Err(format!(
+                    "key origin fingerprint {fingerprint} is not 8 hex characters"
+                ))?;
+            }
+            for step in parts {
+                let number = step.trim_end_matches(['\'', 'h']);
+                if number.parse::<u32>().is_err() || step.len() - number.len() > 1 {
+                    Err(format!("invalid step {step} in key origin"))?;
+                }
+            }
+            (Some(origin.to_string()), rest)
+        }
+        None => (None, s),
+    };
+    let mut parts = rest.split('/');
+    let key = parts.next().unwrap_or_default();
+    if key.len() == 66 || key.len() == 64 {
+        Err("single public keys are not supported, use an extended key with a /* range")?;
+    }
+    let key = xpub::parse(key).map_err(|e| format!("invalid key {key}: {e}"))?;
+    let mut path = vec![];
+    for step in parts {
+        if path.contains(&Step::Wildcard) {
+            Err("* must be the last step of a key path")?;
+        }
+        path.push(match step {
+            "*" => Step::Wildcard,
+            _ if step.ends_with(['\'', 'h']) => Err(format!(
+                "hardened step {step} after the key needs the private key"
+            ))?,
+            _ => match step.strip_prefix('<').and_then(|it| it.strip_suffix('>')) {
+                Some(chains) => {
+                    let (receive, change) = chains
+                        .split_once(';')
+                        .ok_or(format!("{step} must have exactly two paths"))?;
+                    if path.iter().any(|it| matches!(it, Step::Chains(..))) {
+                        Err("a key path can only have one <receive;change> step")?;
+                    }
+                    Step::Chains(parse_step(receive)?, parse_step(change)?)
+                }
+                None => Step::Normal(parse_step(step)?),
+            },
+        });
+    }
+    if !path.contains(&Step::Wildcard) {
+        Err("key path must end with /*, a wallet needs a range of addresses")?;
+    }
+    Ok(Key { origin, key, path })
+}
+
+fn parse_step(step: &str) -> Result<u32> {
+    match step.parse::<u32>() {
+        Ok(n) if n < 1 << 31 => Ok(n),
+        _ => Err(format!("invalid path step {step}"))?,
+    }
+}
+
+/// The 8 character checksum from BIP380.
+fn checksum_of(body: &str) -> Result<String> {
+    let mut symbols = vec![];
+    let mut groups = vec![];
+    for c in body.chars() {
+        let value = INPUT_CHARSET
+            .find(c)
+            .ok_or(format!("descriptor contains invalid character {c:?}"))?
+            as u64;
+        symbols.push(value & 31);
+        groups.push(value >> 5);
+        if groups.len() == 3 {
+            symbols.push(groups[0] * 9 + groups[1] * 3 + groups[2]);
+            groups.clear();
+        }
+    }
+    match groups[..] {
+        [a] => symbols.push(a),
+        [a, b] => symbols.push(a * 3 + b),
+        _ => {}
+    }
+    symbols.extend([0; 8]);
+    let checksum = polymod(&symbols) ^ 1;
+    Ok((0..8)
+        .map(|i| CHECKSUM_CHARSET.as_bytes()[((checksum >> (5 * (7 - i))) & 31) as usize] as char)
+        .collect())
+}
+
+fn polymod(values: &[u64]) -> u64 {
+    const GENERATOR: [u64; 5] = [
+        0xf5dee51989,
+        0xa9fdca3312,
+        0x1bab10e32d,
+        0x3706b1677a,
+        0x644d626ffd,
+    ];
+    let mut chk: u64 = 1;
+    for value in values {
+        let top = chk >> 35;
+        chk = ((chk & 0x7ffffffff) << 5) ^ value;
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                chk ^= generator;
+            }
+        }
+    }
+    chk
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Vectors from BIP380, BIP84 and BIP86
+    const BIP84_XPUB: &str = "xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V";
+    const BIP86_XPUB: &str = "xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYWcLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ";
+
+    #[test]
+    fn computes_checksums() {
+        assert_eq!(
+            checksum_of("pkh([d34db33f/44'/0'/0']xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/1/*)").unwrap(),
+            "ml40v0wf"
+        );
+        assert_eq!(checksum_of("raw(deadbeef)").unwrap(), "89f8spxm");
+    }
+
+    #[test]
+    fn checks_checksum() {
+        let body = format!("wpkh({BIP84_XPUB}/0/*)");
+        let descriptor = parse(&body).unwrap();
+        assert!(descriptor.checksum_added);
+        assert!(parse(&descriptor.text).is_ok());
+        let mut bad = descriptor.text.clone();
+        let last = bad.pop().unwrap();
+        bad.push(if last == 'q' { 'p' } else { 'q' });
+        assert!(parse(&bad).is_err());
+    }
+
+    #[test]
+    fn derives_wpkh_addresses() {
+        let descriptor = parse(&format!("wpkh({BIP84_XPUB}/0/*)")).unwrap();
+        assert!(!descriptor.has_change());
+        assert_eq!(
+            descriptor.address(0, 0).unwrap().to_string(),
+            "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu"
+        );
+    }
+
+    #[test]
+    fn derives_tr_addresses() {
+        let descriptor = parse(&format!("tr({BIP86_XPUB}/<0;1>/*)")).unwrap();
+        assert!(descriptor.has_change());
+        assert_eq!(
+            descriptor.address(0, 0).unwrap().to_string(),
+            "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr"
+        );
+        assert_eq!(
+            descriptor.address(1, 0).unwrap().to_string(),
+            "bc1p3qkhfews2uk44qtvauqyr2ttdsw7svhkl9nkm9s9c3x4ax5h60wqwruhk7"
+        );
+    }
+}
@@ -0,0 +1,718 @@
+use crate::Result;
+use serde_json::Value;
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+};
+
+/// Longitude, latitude.
+pub type Position = [f64; 2];
+pub type Ring = Vec<Position>;
+/// Exterior ring followed by holes.
+pub type Polygon = Vec<Ring>;
+
+pub struct Issue {
+    /// JSON path of the offending value, e.g. $.geometry.coordinates[0][3]
+    pub path: String,
+    pub message: String,
+    /// Warnings are reported but don't block the upload
+    pub warning: bool,
+}
+
+/// Checks that a value is a GeoJSON Feature, a FeatureCollection of them (as
+/// some areas are stored) or a bare geometry with a Polygon or MultiPolygon
+/// geometry, closed rings with enough positions, valid coordinate ranges and
+/// RFC 7946 winding order. Wrong winding is only a warning, the RFC asks
+/// parsers not to reject such input.
+pub fn validate(geo_json: &Value) -> Vec<Issue> {
+    let mut issues = vec![];
+    match geo_json["type"].as_str() {
+        Some("FeatureCollection") => match geo_json["features"].as_array() {
+            Some(features) if !features.is_empty() => {
+                for (i, feature) in features.iter().enumerate() {
+                    validate_feature(feature, &format!("$.features[{i}]"), &mut issues);
+                }
+            }
+            _ => issues.push(error("$.features", "FeatureCollection has no features")),
+        },
+        _ => validate_feature(geo_json, "$", &mut issues),
+    }
+    issues
+}
+
+fn validate_feature(geo_json: &Value, path: &str, issues: &mut Vec<Issue>) {
+    match geo_json["type"].as_str() {
+        Some("Feature") => match geo_json.get("geometry") {
+            Some(geometry) if geometry.is_object() => {
+                validate_geometry(geometry, &format!("{path}.geometry"), issues)
+            }
+            _ => issues.push(error(
+                &format!("{path}.geometry"),
+                "Feature must have a geometry object",
+            )),
+        },
+        Some("Polygon" | "MultiPolygon") => validate_geometry(geo_json, path, issues),
+        Some(other) => issues.push(error(
+            &format!("{path}.type"),
+            &format!("expected Feature, Polygon or MultiPolygon, got {other}"),
+        )),
+        None => issues.push(error(&format!("{path}.type"), "type is missing")),
+    }
+}
+
+fn validate_geometry(geometry: &Value, path: &str, issues: &mut Vec<Issue>) {
+    let coordinates_path = format!("{path}.coordinates");
+    let Some(coordinates) = geometry["coordinates"].as_array() else {
+        issues.push(error(&coordinates_path, "coordinates must be an array"));
+        return;
+    };
+    match geometry["type"].as_str() {
+        Some("Polygon") => validate_polygon(coordinates, &coordinates_path, issues),
+        Some("MultiPolygon") => {
+            if coordinates.is_empty() {
+                issues.push(error(&coordinates_path, "MultiPolygon has no polygons"));
+            }
+            for (i, polygon) in coordinates.iter().enumerate() {
+                let path = format!("{coordinates_path}[{i}]");
+                match polygon.as_array() {
+                    Some(rings) => validate_polygon(rings, &path, issues),
+                    None => issues.push(error(&path, "polygon must be an array of rings")),
+                }
+            }
+        }
+        Some(other) => issues.push(error(
+            &format!("{path}.type"),
+            &format!("expected Polygon or MultiPolygon, got {other}"),
+        )),
+        None => issues.push(error(&format!("{path}.type"), "type is missing")),
+    }
+}
+
+fn validate_polygon(rings: &[Value], path: &str, issues: &mut Vec<Issue>) {
+    if rings.is_empty() {
+        issues.push(error(path, "polygon has no rings"));
+    }
+    for (i, ring) in rings.iter().enumerate() {
+        let path = format!("{path}[{i}]");
+        let Some(positions) = ring.as_array() else {
+            issues.push(error(&path, "ring must be an array of positions"));
+            continue;
+        };
+        let mut parsed: Ring = vec![];
+        for (j, position) in positions.iter().enumerate() {
+            let path = format!("{path}[{j}]");
+            match parse_position(position) {
+                Ok(position) => {
+                    if !(-180.0..=180.0).contains(&position[0]) {
+                        issues.push(error(
+                            &path,
+                            &format!("longitude {} is out of range", position[0]),
+                        ));
+                    }
+                    if !(-90.0..=90.0).contains(&position[1]) {
+                        issues.push(error(
+                            &path,
+                            &format!("latitude {} is out of range", position[1]),
+                        ));
+                    }
+                    parsed.push(position);
+                }
+                Err(e) => issues.push(error(&path, &e)),
+            }
+        }
+        if parsed.len() != positions.len() {
+            continue;
+        }
+        if parsed.len() < 4 {
+            issues.push(error(
+                &path,
+                &format!(
+                    "ring has {} positions, at least 4 are required",
+                    parsed.len()
+                ),
+            ));
+            continue;
+        }
+        if parsed.first() != parsed.last() {
+            issues.push(error(
+                &path,
+                "ring is not closed, the last position must equal the first one",
+            ));
+            continue;
+        }
+        let area = signed_area(&parsed);
+        if i == 0 && area < 0.0 {
+            issues.push(warning(
+                &path,
+                "exterior ring is clockwise, RFC 7946 expects counterclockwise",
+            ));
+        }
+        if i > 0 && area > 0.0 {
+            issues.push(warning(
+                &path,
+                "hole is counterclockwise, RFC 7946 expects clockwise",
+            ));
+        }
+    }
+}
+
+fn parse_position(position: &Value) -> std::result::Result<Position, String> {
+    let Some(items) = position.as_array() else {
+        return Err("position must be an array of numbers".into());
+    };
+    if items.len() < 2 {
+        return Err(format!(
+            "position has {} values, at least 2 are required",
+            items.len()
+        ));
+    }
+    match (items[0].as_f64(), items[1].as_f64()) {
+        (Some(lon), Some(lat)) => Ok([lon, lat]),
+        _ => Err("position values must be numbers".into()),
+    }
+}
+
+fn error(path: &str, message: &str) -> Issue {
+    Issue {
+        path: path.into(),
+        message: message.into(),
+        warning: false,
+    }
+}
+
+fn warning(path: &str, message: &str) -> Issue {
+    Issue {
+        path: path.into(),
+        message: message.into(),
+        warning: true,
+    }
+}
+
+/// Planar shoelace area in squared degrees, positive for counterclockwise rings.
+pub fn signed_area(ring: &[Position]) -> f64 {
+    ring.windows(2)
+        .map(|it| it[0][0] * it[1][1] - it[1][0] * it[0][1])
+        .sum::<f64>()
+        / 2.0
+}
+
+const EARTH_RADIUS_KM: f64 = 6371.0088;
+
+/// Extracts polygons from a FeatureCollection, a Feature, a Polygon or a
+/// MultiPolygon.
+pub fn polygons(geo_json: &Value) -> Result<Vec<Polygon>> {
+    match geo_json["type"].as_str() {
+        Some("FeatureCollection") => Ok(geo_json["features"]
+            .as_array()
+            .ok_or("features must be an array")?
+            .iter()
+            .map(|it| polygons(&it["geometry"]))
+            .collect::<Result<Vec<_>>>()?
+            .concat()),
+        Some("Feature") => polygons(&geo_json["geometry"]),
+        Some("Polygon") => Ok(vec![parse_polygon(&geo_json["coordinates"])?]),
+        Some("MultiPolygon") => geo_json["coordinates"]
+            .as_array()
+            .ok_or("coordinates must be an array")?
+            .iter()
+            .map(parse_polygon)
+            .collect(),
+        _ => Err("expected a Polygon or MultiPolygon geometry")?,
+    }
+}
+
+fn parse_polygon(rings: &Value) -> Result<Polygon> {
+    rings
+        .as_array()
+        .ok_or("polygon must be an array of rings")?
+        .iter()
+        .map(|ring| {
+            ring.as_array()
+                .ok_or("ring must be an array of positions")?
+                .iter()
+                .map(|it| Ok(parse_position(it)?))
+                .collect::<Result<Ring>>()
+        })
+        .collect()
+}
+
+/// Returns a copy of the GeoJSON with every ring replaced by the output of
+/// the given function. Everything else, including Feature properties, is kept.
+pub fn map_rings(geo_json: &Value, f: &dyn Fn(&Ring) -> Ring) -> Result<Value> {
+    let mut res = geo_json.clone();
+    match geo_json["type"].as_str() {
+        Some("FeatureCollection") => {
+            res["features"] = geo_json["features"]
+                .as_array()
+                .ok_or("features must be an array")?
+                .iter()
+                .map(|it| map_rings(it, f))
+                .collect::<Result<_>>()?;
+        }
+        Some("Feature") => res["geometry"] = map_rings(&geo_json["geometry"], f)?,
+        _ => {
+            let polygons = polygons(geo_json)?;
+            let to_json = |polygon: &Polygon| -> Value {
+                Value::Array(
+                    polygon
+                        .iter()
+                        .map(|ring| serde_json::json!(f(ring)))
+                        .collect(),
+                )
+            };
+            res["coordinates"] = match geo_json["type"].as_str() {
+                Some("Polygon") => to_json(&polygons[0]),
+                _ => Value::Array(polygons.iter().map(to_json).collect()),
+            };
+        }
+    }
+    Ok(res)
+}
+
+pub fn vertex_count(polygons: &[Polygon]) -> usize {
+    polygons.iter().flatten().map(|it| it.len()).sum()
+}
+
+/// Geodesic area of a ring on a spherical Earth, in km².
+pub fn ring_area_km2(ring: &[Position]) -> f64 {
+    let sum: f64 = ring
+        .windows(2)
+        .map(|it| {
+            let (lon1, lat1) = (it[0][0].to_radians(), it[0][1].to_radians());
+            let (lon2, lat2) = (it[1][0].to_radians(), it[1][1].to_radians());
+            (lon2 - lon1) * (2.0 + lat1.sin() + lat2.sin())
+        })
+        .sum();
+    (sum * EARTH_RADIUS_KM * EARTH_RADIUS_KM / 2.0).abs()
+}
+
+/// Geodesic area in km², holes are subtracted.
+pub fn area_km2(polygons: &[Polygon]) -> f64 {
+    polygons
+        .iter()
+        .map(|polygon| {
+            polygon
+                .iter()
+                .enumerate()
+                .map(|(i, ring)| match i {
+                    0 => ring_area_km2(ring),
+                    _ => -ring_area_km2(ring),
+                })
+                .sum::<f64>()
+        })
+        .sum()
+}
+
+/// Great-circle distance in km.
+pub fn distance_km(a: Position, b: Position) -> f64 {
+    let (lat1, lat2) = (a[1].to_radians(), b[1].to_radians());
+    let d_lat = lat2 - lat1;
+    let d_lon = (b[0] - a[0]).to_radians();
+    let h = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * h.sqrt().asin()
+}
+
+/// Total length of all rings in km.
+pub fn perimeter_km(polygons: &[Polygon]) -> f64 {
+    polygons
+        .iter()
+        .flatten()
+        .map(|ring| {
+            ring.windows(2)
+                .map(|it| distance_km(it[0], it[1]))
+                .sum::<f64>()
+        })
+        .sum()
+}
+
+/// Douglas-Peucker simplification of a closed ring. Tolerance is the maximum
+/// allowed distance from the original line, in degrees.
+pub fn douglas_peucker(ring: &[Position], tolerance: f64) -> Ring {
+    if ring.len() <= 4 {
+        return ring.to_vec();
+    }
+    // Both ends of a closed ring are the same point, split it at the vertex
+    // farthest from the start so each half has distinct endpoints
+    let split = (1..ring.len() - 1)
+        .max_by(|a, b| {
+            planar_distance(ring[0], ring[*a]).total_cmp(&planar_distance(ring[0], ring[*b]))
+        })
+        .unwrap_or(ring.len() / 2);
+    let mut keep = vec![false; ring.len()];
+    keep[0] = true;
+    keep[split] = true;
+    keep[ring.len() - 1] = true;
+    dp_mark(ring, 0, split, tolerance, &mut keep);
+    dp_mark(ring, split, ring.len() - 1, tolerance, &mut keep);
+    let res: Ring = ring
+        .iter()
+        .zip(&keep)
+        .filter(|(_, keep)| **keep)
+        .map(|(it, _)| *it)
+        .collect();
+    if res.len() < 4 {
+        return ring.to_vec();
+    }
+    res
+}
+
+fn dp_mark(ring: &[Position], start: usize, end: usize, tolerance: f64, keep: &mut [bool]) {
+    if end <= start + 1 {
+        return;
+    }
+    let (index, distance) = (start + 1..end)
+        .map(|i| (i, segment_distance(ring[i], ring[start], ring[end])))
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .unwrap_or((start, 0.0));
+    if distance > tolerance {
+        keep[index] = true;
+        dp_mark(ring, start, index, tolerance, keep);
+        dp_mark(ring, index, end, tolerance, keep);
+    }
+}
+
+/// Visvalingam-Whyatt simplification of a closed ring. Vertices forming a
+/// triangle smaller than the tolerance (in squared degrees) with their
+/// neighbours are removed, smallest first. Triangles sit in a min-heap and
+/// only the two neighbours of a removed vertex are recomputed, so country
+/// sized rings take O(n log n).
+pub fn visvalingam(ring: &[Position], tolerance: f64) -> Ring {
+    // Work on the open ring, the closing position is added back at the end
+    let points = &ring[..ring.len().saturating_sub(1)];
+    let n = points.len();
+    let mut prev: Vec<usize> = (0..n).map(|i| (i + n - 1) % n).collect();
+    let mut next: Vec<usize> = (0..n).map(|i| (i + 1) % n).collect();
+    let mut removed = vec![false; n];
+    // Heap entries of a vertex go stale when a neighbour is removed
+    let mut version = vec![0u32; n];
+    let mut heap: BinaryHeap<Reverse<Triangle>> = (0..n)
+        .map(|i| {
+            Reverse(Triangle {
+                area: triangle_area(points[prev[i]], points[i], points[next[i]]),
+                index: i,
+                version: 0,
+            })
+        })
+        .collect();
+    let mut remaining = n;
+    while remaining > 3 {
+        let Some(Reverse(smallest)) = heap.pop() else {
+            break;
+        };
+        let i = smallest.index;
+        if removed[i] || smallest.version != version[i] {
+            continue;
+        }
+        if smallest.area >= tolerance {
+            break;
+        }
+        removed[i] = true;
+        remaining -= 1;
+        let (before, after) = (prev[i], next[i]);
+        next[before] = after;
+        prev[after] = before;
+        for j in [before, after] {
+            version[j] += 1;
+            heap.push(Reverse(Triangle {
+                area: triangle_area(points[prev[j]], points[j], points[next[j]]),
+                index: j,
+                version: version[j],
+            }));
+        }
+    }
+    let mut res: Ring = (0..n).filter(|i| !removed[*i]).map(|i| points[i]).collect();
+    if let Some(first) = res.first().copied() {
+        res.push(first);
+    }
+    res
+}
+
+/// Visvalingam heap entry, ordered by area and then by position in the ring.
+struct Triangle {
+    area: f64,
+    index: usize,
+    version: u32,
+}
+
+impl Ord for Triangle {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.area
+            .total_cmp(&other.area)
+            .then(self.index.cmp(&other.index))
+    }
+}
+
+impl PartialOrd for Triangle {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Triangle {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Triangle {}
+
+fn planar_distance(a: Position, b: Position) -> f64 {
+    ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2)).sqrt()
+}
+
+fn segment_distance(p: Position, a: Position, b: Position) -> f64 {
+    let (dx, dy) = (b[0] - a[0], b[1] - a[1]);
+    let len2 = dx * dx + dy * dy;
+    if len2 == 0.0 {
+        return planar_distance(p, a);
+    }
+    let t = (((p[0] - a[0]) * dx + (p[1] - a[1]) * dy) / len2).clamp(0.0, 1.0);
+    planar_distance(p, [a[0] + t * dx, a[1] + t * dy])
+}
+
+fn triangle_area(a: Position, b: Position, c: Position) -> f64 {
+    ((b[0] - a[0]) * (c[1] - a[1]) - (c[0] - a[0]) * (b[1] - a[1])).abs() / 2.0
+}
+
+/// West, south, east and north bounds.
+pub fn bbox(polygons: &[Polygon]) -> [f64; 4] {
+    polygons.iter().flatten().flatten().fold(
+        [f64::MAX, f64::MAX, f64::MIN, f64::MIN],
+        |[west, south, east, north], [lon, lat]| {
+            [
+                west.min(*lon),
+                south.min(*lat),
+                east.max(*lon),
+                north.max(*lat),
+            ]
+        },
+    )
+}
+
+/// Area-weighted planar centroid, holes are subtracted.
+pub fn centroid(polygons: &[Polygon]) -> Position {
+    let (mut area, mut x, mut y) = (0.0, 0.0, 0.0);
+    for polygon in polygons {
+        for (i, ring) in polygon.iter().enumerate() {
+            // Normalize orientation so exteriors add and holes subtract
+            let sign = match (i, signed_area(ring) >= 0.0) {
+                (0, true) | (1.., false) => 1.0,
+                _ => -1.0,
+            };
+            for it in ring.windows(2) {
+                let cross = it[0][0] * it[1][1] - it[1][0] * it[0][1];
+                area += sign * cross / 2.0;
+                x += sign * (it[0][0] + it[1][0]) * cross;
+                y += sign * (it[0][1] + it[1][1]) * cross;
+            }
+        }
+    }
+    if area == 0.0 {
+        return [0.0, 0.0];
+    }
+    [x / (6.0 * area), y / (6.0 * area)]
+}
+
+/// Whether a point lies inside any of the polygons, points inside holes
+/// don't count. Uses ray casting on the planar coordinates.
+pub fn contains(polygons: &[Polygon], point: Position) -> bool {
+    polygons.iter().any(|polygon| {
+        polygon
+            .iter()
+            .enumerate()
+            .all(|(i, ring)| ring_contains(ring, point) == (i == 0))
+    })
+}
+
+fn ring_contains(ring: &[Position], [x, y]: Position) -> bool {
+    let mut inside = false;
+    for it in ring.windows(2) {
+        let ([x1, y1], [x2, y2]) = (it[0], it[1]);
+        if (y1 > y) != (y2 > y) && x < (x2 - x1) * (y - y1) / (y2 - y1) + x1 {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn errors(geo_json: &Value) -> Vec<(String, String)> {
+        validate(geo_json)
+            .into_iter()
+            .filter(|it| !it.warning)
+            .map(|it| (it.path, it.message))
+            .collect()
+    }
+
+    fn polygon(ring: Value) -> Value {
+        json!({"type": "Polygon", "coordinates": [ring]})
+    }
+
+    #[test]
+    fn accepts_valid_feature() {
+        let feature = json!({
+            "type": "Feature",
+            "properties": {},
+            "geometry": polygon(json!([[0, 0], [1, 0], [1, 1], [0, 1], [0, 0]])),
+        });
+        assert!(validate(&feature).is_empty());
+    }
+
+    #[test]
+    fn rejects_open_ring() {
+        let errors = errors(&polygon(json!([[0, 0], [1, 0], [1, 1], [0, 1]])));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, "$.coordinates[0]");
+        assert!(errors[0].1.contains("not closed"));
+    }
+
+    #[test]
+    fn rejects_short_ring() {
+        let errors = errors(&polygon(json!([[0, 0], [1, 0], [0, 0]])));
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].1.contains("at least 4"));
+    }
+
+    #[test]
+    fn rejects_out_of_range_coordinates() {
+        let errors = errors(&polygon(json!([[0, 0], [200, 0], [1, 95], [0, 0]])));
+        let paths: Vec<&str> = errors.iter().map(|it| it.0.as_str()).collect();
+        assert_eq!(paths, ["$.coordinates[0][1]", "$.coordinates[0][2]"]);
+        assert!(errors[0].1.contains("longitude"));
+        assert!(errors[1].1.contains("latitude"));
+    }
+
+    #[test]
+    fn rejects_other_geometries() {
+        let errors = errors(&json!({"type": "Point", "coordinates": [0, 0]}));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, "$.type");
+    }
+
+    #[test]
+    fn points_into_feature_collections() {
+        let collection = json!({
+            "type": "FeatureCollection",
+            "features": [
+                {"type": "Feature", "geometry": polygon(json!([[0, 0], [1, 0], [1, 1], [0, 0]]))},
+                {"type": "Feature", "geometry": polygon(json!([[0, 0], [1, 0], [1, 1]]))},
+            ],
+        });
+        let errors = errors(&collection);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, "$.features[1].geometry.coordinates[0]");
+    }
+
+    #[test]
+    fn only_warns_about_winding() {
+        let issues = validate(&polygon(json!([[0, 0], [0, 1], [1, 1], [1, 0], [0, 0]])));
+        assert_eq!(issues.len(), 1);
+        assert!(issues[0].warning);
+        assert!(issues[0].message.contains("clockwise"));
+    }
+
+    /// 2x2 square with a collinear vertex in the middle of every side.
+    const SQUARE: [Position; 9] = [
+        [0.0, 0.0],
+        [1.0, 0.0],
+        [2.0, 0.0],
+        [2.0, 1.0],
+        [2.0, 2.0],
+        [1.0, 2.0],
+        [0.0, 2.0],
+        [0.0, 1.0],
+        [0.0, 0.0],
+    ];
+    const CORNERS: [Position; 5] = [[0.0, 0.0], [2.0, 0.0], [2.0, 2.0], [0.0, 2.0], [0.0, 0.0]];
+    /// Square with a 0.01 degree bump on its bottom side, a triangle of area 0.01.
+    const BUMP: [Position; 6] = [
+        [0.0, 0.0],
+        [1.0, 0.01],
+        [2.0, 0.0],
+        [2.0, 2.0],
+        [0.0, 2.0],
+        [0.0, 0.0],
+    ];
+
+    #[test]
+    fn douglas_peucker_drops_collinear_vertices() {
+        assert_eq!(douglas_peucker(&SQUARE, 0.1), CORNERS);
+    }
+
+    #[test]
+    fn douglas_peucker_keeps_vertices_beyond_tolerance() {
+        assert_eq!(douglas_peucker(&BUMP, 0.001), BUMP);
+        assert_eq!(douglas_peucker(&BUMP, 0.1), CORNERS);
+    }
+
+    #[test]
+    fn visvalingam_drops_collinear_vertices() {
+        assert_eq!(visvalingam(&SQUARE, 0.1), CORNERS);
+    }
+
+    #[test]
+    fn visvalingam_keeps_triangles_above_tolerance() {
+        assert_eq!(visvalingam(&BUMP, 0.005), BUMP);
+        assert_eq!(visvalingam(&BUMP, 0.1), CORNERS);
+    }
+
+    #[test]
+    fn simplified_rings_stay_valid() {
+        let ring = [[0.0, 0.0], [1.0, 0.0], [0.0, 1.0], [0.0, 0.0]];
+        assert_eq!(douglas_peucker(&ring, 10.0), ring);
+        assert_eq!(visvalingam(&ring, 10.0), ring);
+        assert_eq!(visvalingam(&SQUARE, 10.0).len(), 4);
+    }
+
+    /// Removes the smallest triangle, lowest index first on ties, one at a
+    /// time. Quadratic, but obviously what Visvalingam-Whyatt should do.
+    fn naive_visvalingam(ring: &[Position], tolerance: f64) -> Ring {
+        let mut points = ring[..ring.len() - 1].to_vec();
+        let mut indexes: Vec<usize> = (0..points.len()).collect();
+        while points.len() > 3 {
+            let n = points.len();
+            let (i, area) = (0..n)
+                .map(|i| {
+                    (
+                        i,
+                        triangle_area(points[(i + n - 1) % n], points[i], points[(i + 1) % n]),
+                    )
+                })
+                .min_by(|a, b| a.1.total_cmp(&b.1).then(indexes[a.0].cmp(&indexes[b.0])))
+                .unwrap();
+            if area >= tolerance {
+                break;
+            }
+            points.remove(i);
+            indexes.remove(i);
+        }
+        points.push(points[0]);
+        points
+    }
+
+    #[test]
+    fn visvalingam_matches_naive_version() {
+        // Star shaped ring with pseudo-random radii
+        let mut seed: u64 = 42;
+        let mut ring: Ring = (0..300)
+            .map(|i| {
+                seed = seed
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                let radius = 1.0 + (seed >> 33) as f64 / (1u64 << 31) as f64;
+                let angle = i as f64 / 300.0 * std::f64::consts::TAU;
+                [radius * angle.cos(), radius * angle.sin()]
+            })
+            .collect();
+        ring.push(ring[0]);
+        for tolerance in [0.001, 0.01, 0.1] {
+            let simplified = visvalingam(&ring, tolerance);
+            assert!(simplified.len() < ring.len());
+            assert_eq!(simplified, naive_visvalingam(&ring, tolerance));
+        }
+    }
+}
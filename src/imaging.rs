@@ -0,0 +1,92 @@
+use crate::Result;
+use image::{codecs::jpeg::JpegEncoder, imageops::FilterType, DynamicImage, ImageFormat};
+use std::io::Cursor;
+
+/// Formats the server accepts, with the names used on the command line.
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    Png,
+    Jpeg,
+    Webp,
+}
+
+impl Format {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Format::Png => "png",
+            Format::Jpeg => "jpeg",
+            Format::Webp => "webp",
+        }
+    }
+
+    fn image_format(&self) -> ImageFormat {
+        match self {
+            Format::Png => ImageFormat::Png,
+            Format::Jpeg => ImageFormat::Jpeg,
+            Format::Webp => ImageFormat::WebP,
+        }
+    }
+}
+
+/// Detects the image type from the first bytes of a file, so a mislabeled
+/// file extension doesn't matter. Known but unsupported types are reported by
+/// name.
+pub fn detect(bytes: &[u8]) -> Result<Format> {
+    let unsupported = |name: &str| -> Result<Format> {
+        Err(format!(
+            "{name} images are not supported, use png, jpeg or webp"
+        ))?
+    };
+    match bytes {
+        [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, ..] => Ok(Format::Png),
+        [0xFF, 0xD8, 0xFF, ..] => Ok(Format::Jpeg),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Ok(Format::Webp),
+        [b'G', b'I', b'F', b'8', ..] => unsupported("gif"),
+        [b'B', b'M', ..] => unsupported("bmp"),
+        [0x49, 0x49, 0x2A, 0x00, ..] | [0x4D, 0x4D, 0x00, 0x2A, ..] => unsupported("tiff"),
+        [_, _, _, _, b'f', b't', b'y', b'p', ..] => unsupported("heif/avif"),
+        _ if bytes.trim_ascii_start().starts_with(b"<") => unsupported("svg"),
+        _ => Err("unrecognized image type, use png, jpeg or webp")?,
+    }
+}
+
+pub fn decode(bytes: &[u8], format: Format) -> Result<DynamicImage> {
+    Ok(
+        image::load_from_memory_with_format(bytes, format.image_format())
+            .map_err(|e| format!("failed to decode {} image: {e}", format.name()))?,
+    )
+}
+
+/// Cuts the largest centered square out of an image.
+pub fn crop_square(image: &DynamicImage) -> DynamicImage {
+    let side = image.width().min(image.height());
+    image.crop_imm(
+        (image.width() - side) / 2,
+        (image.height() - side) / 2,
+        side,
+        side,
+    )
+}
+
+/// Scales an image down, keeping its aspect ratio, so that neither side
+/// exceeds max_side. Smaller images are left as they are.
+pub fn downscale(image: DynamicImage, max_side: u32) -> DynamicImage {
+    if image.width() <= max_side && image.height() <= max_side {
+        return image;
+    }
+    image.resize(max_side, max_side, FilterType::Lanczos3)
+}
+
+/// Webp is always written lossless, quality only applies to jpeg.
+pub fn encode(image: &DynamicImage, format: Format, quality: u8) -> Result<Vec<u8>> {
+    let mut res = Cursor::new(vec![]);
+    match format {
+        Format::Jpeg => {
+            // Jpeg has no alpha channel
+            JpegEncoder::new_with_quality(&mut res, quality.clamp(1, 100))
+                .encode_image(&DynamicImage::ImageRgb8(image.to_rgb8()))?
+        }
+        _ => image.write_to(&mut res, format.image_format())?,
+    }
+    Ok(res.into_inner())
+}
@@ -0,0 +1,93 @@
+use crate::{settings, Result};
+use rusqlite::{params, Connection, Row};
+use serde_json::Value;
+
+/// A recorded tag mutation. Missing values mean the tag didn't exist before
+/// or was removed.
+pub struct Entry {
+    pub id: i64,
+    pub target: String,
+    pub target_id: String,
+    pub tag: String,
+    pub old_value: Option<Value>,
+    pub new_value: Option<Value>,
+    pub created_at: String,
+    pub undone_by: Option<i64>,
+}
+
+/// Records a tag change on an element or an area and returns the entry id.
+pub fn record(
+    target: &str,
+    target_id: &str,
+    tag: &str,
+    old_value: Option<&Value>,
+    new_value: Option<&Value>,
+) -> Result<i64> {
+    let conn = connect()?;
+    conn.execute(
+        "INSERT INTO tag_journal (server, target, target_id, tag, old_value, new_value, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, strftime('%Y-%m-%dT%H:%M:%SZ'));",
+        params![
+            settings::api_url()?.value,
+            target,
+            target_id,
+            tag,
+            old_value.map(|it| it.to_string()),
+            new_value.map(|it| it.to_string()),
+        ],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+/// Latest entries for the current server, newest first.
+pub fn entries(limit: i64) -> Result<Vec<Entry>> {
+    let conn = connect()?;
+    let mut stmt = conn.prepare(
+        "SELECT id, target, target_id, tag, old_value, new_value, created_at, undone_by FROM tag_journal WHERE server = ?1 ORDER BY id DESC LIMIT ?2;",
+    )?;
+    let res = stmt
+        .query_map(params![settings::api_url()?.value, limit], entry)?
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    Ok(res)
+}
+
+pub fn get(id: i64) -> Result<Entry> {
+    let conn = connect()?;
+    let mut stmt = conn.prepare(
+        "SELECT id, target, target_id, tag, old_value, new_value, created_at, undone_by FROM tag_journal WHERE server = ?1 AND id = ?2;",
+    )?;
+    let mut rows = stmt.query_map(params![settings::api_url()?.value, id], entry)?;
+    Ok(rows
+        .next()
+        .ok_or(format!("journal entry {id} does not exist"))??)
+}
+
+pub fn mark_undone(id: i64, undone_by: i64) -> Result<()> {
+    connect()?.execute(
+        "UPDATE tag_journal SET undone_by = ?2 WHERE id = ?1;",
+        params![id, undone_by],
+    )?;
+    Ok(())
+}
+
+fn entry(row: &Row) -> rusqlite::Result<Entry> {
+    let json = |it: Option<String>| it.and_then(|it| serde_json::from_str(&it).ok());
+    Ok(Entry {
+        id: row.get(0)?,
+        target: row.get(1)?,
+        target_id: row.get(2)?,
+        tag: row.get(3)?,
+        old_value: json(row.get(4)?),
+        new_value: json(row.get(5)?),
+        created_at: row.get(6)?,
+        undone_by: row.get(7)?,
+    })
+}
+
+fn connect() -> Result<Connection> {
+    let conn = settings::connect()?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS tag_journal (id INTEGER PRIMARY KEY, server TEXT NOT NULL, target TEXT NOT NULL, target_id TEXT NOT NULL, tag TEXT NOT NULL, old_value TEXT, new_value TEXT, created_at TEXT NOT NULL, undone_by INTEGER);",
+        (),
+    )?;
+    Ok(conn)
+}
@@ -0,0 +1,17 @@
+//! Client for the BTC Map JSON RPC API, shared by the btcmap-cli binary.
+//!
+//! ```no_run
+//! use btcmap_cli::{types::GetElementParams, Client};
+//!
+//! let client = Client::new("https://api.btcmap.org/rpc").with_token("secret");
+//! let element = client.get_element(&GetElementParams { id: 1 })?;
+//! println!("{:?}", element.tags.get("name"));
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+pub mod client;
+pub mod types;
+
+pub use client::{is_read_only, Client, DryRun, RpcError, RpcResponse, TransportError};
+
+pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
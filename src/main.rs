@@ -1,13 +1,26 @@
-use std::{env, error::Error};
+use std::{env, error::Error, process::ExitCode, time::Duration};
+mod bolt11;
+mod cache;
 mod command;
+mod credentials;
+mod csv;
+mod descriptor;
+mod geo;
+mod imaging;
+mod journal;
+mod output;
+mod prompt;
 mod rpc;
 mod settings;
-use clap::{Arg, ArgAction, ArgMatches, Command, FromArgMatches, Subcommand};
+mod xpub;
+use clap::{Arg, ArgAction, ArgMatches, Args, Command, FromArgMatches, Subcommand, ValueEnum};
+use clap_complete::{engine::ArgValueCandidates, CompleteEnv};
 use command::area;
 use command::element;
 
 type Result<T> = std::result::Result<T, Box<dyn Error>>;
 
+#[allow(clippy::enum_variant_names)]
 mod sections {
     use crate::command;
     use clap::Subcommand;
@@ -38,6 +51,8 @@ mod sections {
         SetElementTag(command::element::SetElementTagArgs),
         /// Remove tag from a certain element
         RemoveElementTag(command::element::RemoveElementTagArgs),
+        /// Set tags on many elements from CSV or NDJSON rows. Every value is validated as JSON before anything is sent
+        BulkTag(command::element::BulkTagArgs),
         /// Get all boosted elements
         GetBoostedElements,
         /// Boost an element for a set number of days. You can use either numeric or OSM (node:12345) id
@@ -66,14 +81,28 @@ mod sections {
     pub enum Area {
         /// Fetch area by either numeric id or string alias (th)
         GetArea(command::area::GetAreaArgs),
-        /// Create a new area. Alias is required; geojson must be a valid GeoJSON Feature object
+        /// Download area geometries as a GeoJSON FeatureCollection with area tags as properties, ready for QGIS or JOSM
+        Export(command::area::ExportArgs),
+        /// Simplify area geometry locally and report vertex count, size, area and perimeter changes. Use --upload to save the result
+        Simplify(command::area::SimplifyArgs),
+        /// Compute bbox, centroid, geodesic area, ring and vertex counts locally. Use --check to spot stale stored bboxes
+        GeometryInfo(command::area::GeometryInfoArgs),
+        /// List areas containing a coordinate. Uses areas cached by earlier fetches (export, geometry-info, --fetch) or exported GeoJSON files
+        Locate(command::area::LocateArgs),
+        /// Save the current state of areas locally so later changes can be diffed
+        Snapshot(command::area::SnapshotArgs),
+        /// Compare the current server state of an area with a saved snapshot or a JSON file. Geometry changes are summarized
+        Diff(command::area::DiffArgs),
+        /// List saved snapshots of an area and the tags changed between them
+        History(command::area::HistoryArgs),
+        /// Create a new area. Alias is required; geojson must be a valid GeoJSON Feature with a Polygon or MultiPolygon geometry and is validated locally before upload
         #[command(name = "add")]
         AddArea(command::area::AddAreaArgs),
         /// Set tag to a certain area. You can use either numeric id or a string alias (th)
         SetAreaTag(command::area::SetAreaTagArgs),
         /// Remove tag from a certain area. You can use either numeric id or a string alias (th)
         RemoveAreaTag(command::area::RemoveAreaTagArgs),
-        /// Set an image for an area. You can use either numeric id or a string alias. The image file is read from disk; its format is auto-detected server-side
+        /// Set an image for an area. You can use either numeric id or a string alias. The png, jpeg or webp file is checked locally and can be cropped, downscaled and re-encoded before upload
         SetAreaImage(command::area::SetAreaImageArgs),
         /// Ensure that elements and areas are correctly mapped to each other. You need to provide element id range in order to operate on a specific slice of elements
         GenerateElementMapping,
@@ -95,6 +124,8 @@ mod sections {
         SyncUnpaidInvoices(command::admin::SyncUnpaidInvoicesArgs),
         /// Get invoice details, you only need to pass invoice ID (integer)
         GetInvoice(command::admin::GetInvoiceArgs),
+        /// Fetch and cache invoices by id range, then print totals by status and description prefix
+        Invoices(command::admin::InvoicesArgs),
         SetApiKey(command::admin::SetApiKeyArgs),
     }
 
@@ -171,6 +202,12 @@ mod sections {
         Update(command::wallet::UpdateArgs),
         /// Soft-delete a wallet. Use --include-deleted when listing to see it again.
         Remove(command::wallet::RemoveArgs),
+        /// Validate an extended public key or output descriptor and print its first receive and change addresses, without calling the server
+        Derive(command::wallet::DeriveArgs),
+        /// Total confirmed and unconfirmed balances of all wallets that aren't soft-deleted and flag stale ones
+        Summary(command::wallet::SummaryArgs),
+        /// List balance snapshots recorded with summary --snapshot, or draw them as a chart with --graph
+        History(command::wallet::HistoryArgs),
     }
 
     #[derive(Subcommand)]
@@ -179,6 +216,10 @@ mod sections {
         SetServer(command::setup::SetServerArgs),
         /// Show all locally cached data
         State(command::setup::StateArgs),
+        /// Manage named server and account profiles
+        Profile(command::setup::ProfileArgs),
+        /// Choose where the API token of the active profile is stored. The current token is moved to the new backend
+        SetCredentialBackend(command::setup::SetCredentialBackendArgs),
     }
 
     #[derive(Subcommand)]
@@ -205,10 +246,28 @@ mod sections {
         Event(command::common::SearchArgs),
     }
 
+    #[derive(Subcommand)]
+    pub enum History {
+        /// List recent tag changes made from this machine, newest first
+        List(command::history::ListArgs),
+        /// Restore the value a tag had before a journal entry. Refuses if the tag was changed again since, unless --force is passed
+        Undo(command::history::UndoArgs),
+    }
+
+    #[derive(Subcommand)]
+    pub enum Invoice {
+        /// Decode a BOLT11 invoice locally: amount, expiry, payment hash and description
+        Decode(command::invoice::DecodeArgs),
+        /// Poll get_invoice until an invoice is paid or expired. Exits with 8 if it expires
+        Wait(command::invoice::WaitArgs),
+    }
+
     #[derive(Subcommand)]
     pub enum Common {
         /// Custom RPC
-        RPC(command::common::CustomArgs),
+        Rpc(command::common::CustomArgs),
+        /// Send many calls as JSON-RPC batch requests and print one result per line, in input order
+        Batch(command::common::BatchArgs),
     }
 }
 
@@ -223,6 +282,7 @@ fn build_cli() -> Command {
     Command::new("btcmap-cli")
         .about("Manage BTC Map server via JSON RPC API")
         .version(env!("CARGO_PKG_VERSION"))
+        .after_help(rpc::EXIT_CODES_HELP)
         .arg(
             Arg::new("verbose")
                 .short('v')
@@ -230,6 +290,80 @@ fn build_cli() -> Command {
                 .action(ArgAction::Count)
                 .help("Increase verbosity"),
         )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .global(true)
+                .value_name("FORMAT")
+                .value_parser(clap::value_parser!(output::Format))
+                .default_value("json-pretty")
+                .help(
+                    "Output format. Arrays of objects are flattened into columns for csv and table",
+                ),
+        )
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .global(true)
+                .value_name("NAME")
+                .add(ArgValueCandidates::new(command::shell::profile_candidates))
+                .help("Use a named profile instead of the active one. Can also be set with BTCMAP_PROFILE"),
+        )
+        .arg(
+            Arg::new("server")
+                .long("server")
+                .global(true)
+                .value_name("URL")
+                .help("JSON RPC API URL for this invocation, prod and dev shortcuts are supported. Can also be set with BTCMAP_API_URL"),
+        )
+        .arg(
+            Arg::new("api-key")
+                .long("api-key")
+                .global(true)
+                .value_name("KEY")
+                .help("API token for this invocation. Can also be set with BTCMAP_API_KEY"),
+        )
+        .arg(
+            Arg::new("timeout")
+                .long("timeout")
+                .global(true)
+                .value_name("SECONDS")
+                .value_parser(positive_seconds)
+                .help("Give up on a request after this many seconds. No limit by default"),
+        )
+        .arg(
+            Arg::new("retries")
+                .long("retries")
+                .global(true)
+                .value_name("COUNT")
+                .value_parser(clap::value_parser!(u32))
+                .default_value("0")
+                .help("Retry failed read-only requests (get_*, search) on connection failures, timeouts, HTTP 429 and 5xx"),
+        )
+        .arg(
+            Arg::new("retry-backoff")
+                .long("retry-backoff")
+                .global(true)
+                .value_name("SECONDS")
+                .value_parser(seconds)
+                .default_value("1")
+                .help("Delay before the first retry, doubled after every attempt"),
+        )
+        .arg(
+            Arg::new("retry-mutations")
+                .long("retry-mutations")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help("Also retry methods that change server state. They may end up applied twice"),
+        )
+        .arg(
+            Arg::new("dry-run")
+                .long("dry-run")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help("Print the method and params of every call that would change server state and exit without sending it. Read-only calls are still sent, but nothing is written to the local cache or snapshots"),
+        )
         .subcommand_required(true)
         .arg_required_else_help(true)
         .subcommand(sections::Auth::augment_subcommands(section(
@@ -288,21 +422,113 @@ fn build_cli() -> Command {
             "search",
             "Search areas, places and events",
         )))
+        .subcommand(sections::History::augment_subcommands(section(
+            "history",
+            "Local journal of element and area tag changes",
+        )))
+        .subcommand(sections::Invoice::augment_subcommands(section(
+            "invoice",
+            "Lightning invoices",
+        )))
         .subcommand(sections::Common::augment_subcommands(section(
             "common",
             "General-purpose commands",
         )))
+        .subcommand(command::shell::CompletionsArgs::augment_args(
+            Command::new("completions").about(
+                "Print a shell completion script, e.g. add source <(btcmap-cli completions bash) to ~/.bashrc",
+            ),
+        ))
+        .subcommand(command::shell::ManpageArgs::augment_args(
+            Command::new("manpage").about("Render roff man pages for the CLI and every section"),
+        ))
 }
 
-fn main() -> Result<()> {
+fn main() -> ExitCode {
+    // Answers completion requests from the script printed by the completions command
+    CompleteEnv::with_factory(build_cli).complete();
+    match run().or_else(print_dry_run) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            match e.downcast_ref::<rpc::RpcError>() {
+                Some(rpc_error) => match serde_json::to_string(&rpc_error.to_json()) {
+                    Ok(json) => eprintln!("{json}"),
+                    Err(_) => eprintln!("Error: {rpc_error}"),
+                },
+                None => eprintln!("Error: {e}"),
+            }
+            ExitCode::from(rpc::exit_code(e.as_ref()))
+        }
+    }
+}
+
+/// Mutations stopped by --dry-run end up here, their plan is the output.
+fn print_dry_run(e: Box<dyn Error>) -> Result<()> {
+    match e.downcast_ref::<rpc::DryRun>() {
+        Some(dry_run) => output::print(&dry_run.plan),
+        None => Err(e),
+    }
+}
+
+fn run() -> Result<()> {
     let matches = build_cli().get_matches();
     let verbose = matches.get_count("verbose");
     unsafe { std::env::set_var("VERBOSITY", verbose.to_string()) };
+    if let Some(format) = matches
+        .get_one::<output::Format>("output")
+        .and_then(|it| it.to_possible_value())
+    {
+        unsafe { std::env::set_var("OUTPUT_FORMAT", format.get_name()) };
+    }
+    if let Some(profile) = matches.get_one::<String>("profile") {
+        unsafe { std::env::set_var("BTCMAP_PROFILE", profile) };
+    }
+    if let Some(server) = matches.get_one::<String>("server") {
+        unsafe { std::env::set_var("SERVER_OVERRIDE", server) };
+    }
+    if let Some(api_key) = matches.get_one::<String>("api-key") {
+        unsafe { std::env::set_var("API_KEY_OVERRIDE", api_key) };
+    }
+    if let Some(timeout) = matches.get_one::<f64>("timeout") {
+        unsafe { std::env::set_var("HTTP_TIMEOUT", timeout.to_string()) };
+    }
+    if let Some(retries) = matches.get_one::<u32>("retries") {
+        unsafe { std::env::set_var("HTTP_RETRIES", retries.to_string()) };
+    }
+    if let Some(backoff) = matches.get_one::<f64>("retry-backoff") {
+        unsafe { std::env::set_var("HTTP_RETRY_BACKOFF", backoff.to_string()) };
+    }
+    if matches.get_flag("retry-mutations") {
+        unsafe { std::env::set_var("RETRY_MUTATIONS", "1") };
+    }
 
     let (section, sub_matches) = matches
         .subcommand()
         .expect("subcommand_required(true) guarantees a section");
 
+    match section {
+        "completions" => {
+            let args = command::shell::CompletionsArgs::from_arg_matches(sub_matches)?;
+            return command::shell::completions(&args);
+        }
+        "manpage" => {
+            let args = command::shell::ManpageArgs::from_arg_matches(sub_matches)?;
+            return command::shell::manpage(&args, build_cli());
+        }
+        _ => {}
+    }
+
+    let dry_run = matches.get_flag("dry-run") && is_mutation(&command_path(section, sub_matches));
+    if dry_run
+        && (section == "setup" || (section, command_name(sub_matches)) == ("admin", "set-api-key"))
+    {
+        Err("--dry-run doesn't apply to commands that only change local settings")?;
+    }
+    // Read-only commands see it too, so they skip their local cache writes
+    if matches.get_flag("dry-run") {
+        unsafe { std::env::set_var("DRY_RUN", "1") };
+    }
+
     if let Some((cmd, cmd_matches)) = sub_matches.subcommand() {
         match (section, cmd) {
             ("setup", "set-server") => {
@@ -313,6 +539,26 @@ fn main() -> Result<()> {
                 let args = command::setup::StateArgs::from_arg_matches(cmd_matches)?;
                 return command::setup::state(&args);
             }
+            ("setup", "profile") => {
+                let args = command::setup::ProfileArgs::from_arg_matches(cmd_matches)?;
+                return command::setup::profile(&args);
+            }
+            ("setup", "set-credential-backend") => {
+                let args = command::setup::SetCredentialBackendArgs::from_arg_matches(cmd_matches)?;
+                return command::setup::set_credential_backend(&args);
+            }
+            ("wallet", "derive") => {
+                let args = command::wallet::DeriveArgs::from_arg_matches(cmd_matches)?;
+                return command::wallet::derive(&args);
+            }
+            ("wallet", "history") => {
+                let args = command::wallet::HistoryArgs::from_arg_matches(cmd_matches)?;
+                return command::wallet::history(&args);
+            }
+            ("invoice", "decode") => {
+                let args = command::invoice::DecodeArgs::from_arg_matches(cmd_matches)?;
+                return command::invoice::decode(&args);
+            }
             ("auth", "signin") => {
                 let args = command::auth::SignInArgs::from_arg_matches(cmd_matches)?;
                 return command::auth::sign_in(&args);
@@ -329,7 +575,7 @@ fn main() -> Result<()> {
         }
     }
 
-    if settings::get_str("password")?.is_empty() {
+    if !dry_run && settings::api_key()?.value.is_empty() {
         Err("you need to sign in first, run btcmap-cli auth signin <username> <password>")?;
     }
 
@@ -353,6 +599,7 @@ fn dispatch(section: &str, sub_matches: &ArgMatches) -> Result<()> {
             sections::Element::GetElement(args) => element::get_element(&args),
             sections::Element::SetElementTag(args) => element::set_element_tag(&args),
             sections::Element::RemoveElementTag(args) => element::remove_element_tag(&args),
+            sections::Element::BulkTag(args) => element::bulk_tag(&args),
             sections::Element::GetBoostedElements => element::get_boosted_elements(),
             sections::Element::BoostElement(args) => element::boost_element(&args),
             sections::Element::PaywallGetBoostElementQuote => {
@@ -377,6 +624,13 @@ fn dispatch(section: &str, sub_matches: &ArgMatches) -> Result<()> {
         },
         "area" => match sections::Area::from_arg_matches(sub_matches)? {
             sections::Area::GetArea(args) => area::get_area(&args),
+            sections::Area::Export(args) => area::export(&args),
+            sections::Area::Simplify(args) => area::simplify(&args),
+            sections::Area::GeometryInfo(args) => area::geometry_info(&args),
+            sections::Area::Locate(args) => area::locate(&args),
+            sections::Area::Snapshot(args) => area::snapshot(&args),
+            sections::Area::Diff(args) => area::diff(&args),
+            sections::Area::History(args) => area::history(&args),
             sections::Area::AddArea(args) => area::add_area(&args),
             sections::Area::SetAreaTag(args) => area::set_area_tag(&args),
             sections::Area::RemoveAreaTag(args) => area::remove_area_tag(&args),
@@ -393,6 +647,7 @@ fn dispatch(section: &str, sub_matches: &ArgMatches) -> Result<()> {
                 command::admin::sync_unpaid_invoices(&args)
             }
             sections::Admin::GetInvoice(args) => command::admin::get_invoice(&args),
+            sections::Admin::Invoices(args) => command::admin::invoices(&args),
             sections::Admin::SetApiKey(args) => command::admin::set_api_key(&args),
         },
         "dashboard" => match sections::Dashboard::from_arg_matches(sub_matches)? {
@@ -446,9 +701,15 @@ fn dispatch(section: &str, sub_matches: &ArgMatches) -> Result<()> {
             sections::Wallet::Add(args) => command::wallet::add(&args),
             sections::Wallet::Update(args) => command::wallet::update(&args),
             sections::Wallet::Remove(args) => command::wallet::remove(&args),
+            sections::Wallet::Derive(args) => command::wallet::derive(&args),
+            sections::Wallet::Summary(args) => command::wallet::summary(&args),
+            sections::Wallet::History(args) => command::wallet::history(&args),
         },
         "setup" => match sections::Setup::from_arg_matches(sub_matches)? {
-            sections::Setup::SetServer(_) | sections::Setup::State(_) => {
+            sections::Setup::SetServer(_)
+            | sections::Setup::State(_)
+            | sections::Setup::Profile(_)
+            | sections::Setup::SetCredentialBackend(_) => {
                 unreachable!("pre-auth variants handled above")
             }
         },
@@ -466,13 +727,104 @@ fn dispatch(section: &str, sub_matches: &ArgMatches) -> Result<()> {
             sections::Search::Place(args) => command::common::search_typed(&args, "place"),
             sections::Search::Event(args) => command::common::search_typed(&args, "event"),
         },
+        "history" => match sections::History::from_arg_matches(sub_matches)? {
+            sections::History::List(args) => command::history::list(&args),
+            sections::History::Undo(args) => command::history::undo(&args),
+        },
+        "invoice" => match sections::Invoice::from_arg_matches(sub_matches)? {
+            sections::Invoice::Wait(args) => command::invoice::wait_for(&args),
+            sections::Invoice::Decode(_) => unreachable!("pre-auth variants handled above"),
+        },
         "common" => match sections::Common::from_arg_matches(sub_matches)? {
-            sections::Common::RPC(args) => command::common::rpc(&args),
+            sections::Common::Rpc(args) => command::common::rpc(&args),
+            sections::Common::Batch(args) => command::common::batch(&args),
         },
         _ => unreachable!("all sections are explicitly matched"),
     }
 }
 
+fn command_name(matches: &ArgMatches) -> &str {
+    matches.subcommand_name().unwrap_or_default()
+}
+
+fn command_path<'a>(section: &'a str, mut matches: &'a ArgMatches) -> Vec<&'a str> {
+    let mut path = vec![section];
+    while let Some((name, sub_matches)) = matches.subcommand() {
+        path.push(name);
+        matches = sub_matches;
+    }
+    path
+}
+
+/// Whether a command changes server state or local settings. Commands that
+/// accept arbitrary methods count as mutations; the RPC layer then lets their
+/// read-only calls through. Anything not listed here is treated as a mutation.
+/// Commands listed here that write the local cache skip that under --dry-run.
+fn is_mutation(path: &[&str]) -> bool {
+    !matches!(
+        path,
+        ["auth", "get-api-keys" | "whoami"]
+            | [
+                "element",
+                "get-element"
+                    | "get-boosted-elements"
+                    | "paywall-get-boost-element-quote"
+                    | "paywall-get-add-element-comment-quote"
+            ]
+            | [
+                "area",
+                "get-area"
+                    | "export"
+                    | "geometry-info"
+                    | "locate"
+                    | "snapshot"
+                    | "diff"
+                    | "history"
+            ]
+            | ["admin", "get-invoice" | "invoices"]
+            | ["dashboard", _]
+            | [
+                "report",
+                "get-trending-countries"
+                    | "get-trending-communities"
+                    | "get-most-commented-countries"
+                    | "get-daily-infra-report"
+                    | "get-top-clients"
+                    | "get-report"
+            ]
+            | ["event", "get-events" | "get-event"]
+            | ["place-import", "get-submitted-place" | "list-origins"]
+            | ["electrum-server", "list"]
+            | ["wallet", "list"]
+            | ["wallet", "derive" | "summary" | "history"]
+            | ["setup", "state"]
+            | ["setup", "profile", "list"]
+            | ["user", _]
+            | ["search", _]
+            | ["history", "list"]
+            | ["invoice", _]
+    )
+}
+
+/// Parses a non-negative number of seconds that fits in a Duration.
+pub fn seconds(s: &str) -> std::result::Result<f64, String> {
+    let seconds: f64 = s.parse().map_err(|_| format!("{s} is not a number"))?;
+    if seconds < 0.0 || Duration::try_from_secs_f64(seconds).is_err() {
+        Err(format!("{s} is not a non-negative number of seconds"))?;
+    }
+    Ok(seconds)
+}
+
+/// Same as seconds, for timeouts and intervals where 0 would fail every
+/// request or poll in a busy loop.
+pub fn positive_seconds(s: &str) -> std::result::Result<f64, String> {
+    let seconds = crate::seconds(s)?;
+    if seconds == 0.0 {
+        Err(format!("{s} is not a positive number of seconds"))?;
+    }
+    Ok(seconds)
+}
+
 pub fn verbosity() -> i64 {
     env::var("VERBOSITY")
         .unwrap_or("".into())
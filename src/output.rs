@@ -0,0 +1,173 @@
+use crate::{csv, Result};
+use clap::ValueEnum;
+use colored_json::ToColoredJson;
+use serde_json::{Map, Value};
+use std::env;
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// Compact single-line JSON
+    Json,
+    /// Indented JSON, colored when printing to a terminal
+    JsonPretty,
+    /// One compact JSON value per line; arrays are split into their items
+    Ndjson,
+    /// Comma-separated values with a header row; nested objects become dotted columns
+    Csv,
+    /// Aligned plain-text table; nested objects become dotted columns
+    Table,
+    /// Strings without quotes, everything else as compact JSON
+    Raw,
+}
+
+pub fn format() -> Format {
+    env::var("OUTPUT_FORMAT")
+        .ok()
+        .and_then(|it| Format::from_str(&it, true).ok())
+        .unwrap_or(Format::JsonPretty)
+}
+
+pub fn print(value: &Value) -> Result<()> {
+    println!("{}", render(value, format())?);
+    Ok(())
+}
+
+pub fn render(value: &Value, format: Format) -> Result<String> {
+    let res = match format {
+        Format::Json => serde_json::to_string(value)?,
+        Format::JsonPretty => serde_json::to_string(value)?.to_colored_json_auto()?,
+        Format::Ndjson => match value {
+            Value::Array(items) => items
+                .iter()
+                .map(serde_json::to_string)
+                .collect::<std::result::Result<Vec<_>, _>>()?
+                .join("\n"),
+            _ => serde_json::to_string(value)?,
+        },
+        Format::Csv => {
+            let (columns, rows) = flatten_rows(value);
+            let mut lines = vec![columns.iter().map(|it| csv::field(it)).collect::<Vec<_>>()];
+            for row in rows {
+                lines.push(row.iter().map(|it| csv::field(it)).collect());
+            }
+            lines
+                .iter()
+                .map(|it| it.join(","))
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+        Format::Table => {
+            let (columns, rows) = flatten_rows(value);
+            let mut widths: Vec<usize> = columns.iter().map(|it| it.chars().count()).collect();
+            for row in &rows {
+                for (i, cell) in row.iter().enumerate() {
+                    widths[i] = widths[i].max(cell.chars().count());
+                }
+            }
+            let mut lines = vec![table_line(&columns, &widths)];
+            lines.push(
+                widths
+                    .iter()
+                    .map(|it| "-".repeat(*it))
+                    .collect::<Vec<_>>()
+                    .join("  "),
+            );
+            for row in &rows {
+                lines.push(table_line(row, &widths));
+            }
+            lines.join("\n")
+        }
+        Format::Raw => match value {
+            Value::String(it) => it.clone(),
+            Value::Array(items) => items
+                .iter()
+                .map(|it| match it {
+                    Value::String(it) => it.clone(),
+                    _ => it.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            _ => value.to_string(),
+        },
+    };
+    Ok(res)
+}
+
+/// Turns a JSON value into a header and a list of rows. Arrays of objects
+/// become one row per item, a single object becomes one row and scalars end
+/// up in a single "value" column.
+pub fn flatten_rows(value: &Value) -> (Vec<String>, Vec<Vec<String>>) {
+    let items: Vec<Map<String, Value>> = match value {
+        Value::Array(items) => items.iter().map(flatten).collect(),
+        _ => vec![flatten(value)],
+    };
+    let mut columns: Vec<String> = vec![];
+    for item in &items {
+        for key in item.keys() {
+            if !columns.contains(key) {
+                columns.push(key.clone());
+            }
+        }
+    }
+    let rows = items
+        .iter()
+        .map(|item| {
+            columns
+                .iter()
+                .map(|column| item.get(column).map(cell).unwrap_or_default())
+                .collect()
+        })
+        .collect();
+    (columns, rows)
+}
+
+fn flatten(value: &Value) -> Map<String, Value> {
+    let mut res = Map::new();
+    match value {
+        Value::Object(_) => flatten_into(&mut res, "", value),
+        _ => {
+            res.insert("value".into(), value.clone());
+        }
+    }
+    res
+}
+
+fn flatten_into(res: &mut Map<String, Value>, prefix: &str, value: &Value) {
+    match value {
+        Value::Object(fields) if !fields.is_empty() => {
+            for (key, value) in fields {
+                let key = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{prefix}.{key}")
+                };
+                flatten_into(res, &key, value);
+            }
+        }
+        _ => {
+            res.insert(prefix.into(), value.clone());
+        }
+    }
+}
+
+fn cell(value: &Value) -> String {
+    match value {
+        Value::Null => "".into(),
+        Value::String(it) => it.clone(),
+        _ => value.to_string(),
+    }
+}
+
+fn table_line(cells: &[String], widths: &[usize]) -> String {
+    cells
+        .iter()
+        .zip(widths)
+        .map(|(cell, width)| {
+            let cell = cell.replace(['\n', '\r'], " ");
+            format!("{cell:<width$}")
+        })
+        .collect::<Vec<_>>()
+        .join("  ")
+        .trim_end()
+        .into()
+}
@@ -0,0 +1,36 @@
+use crate::Result;
+use std::io::{stdin, IsTerminal, Read};
+
+/// Asks for a secret on the terminal with echo disabled. New secrets are
+/// requested twice to catch typos.
+pub fn secret(prompt: &str, confirm: bool) -> Result<String> {
+    if !stdin().is_terminal() {
+        Err(format!(
+            "{} is required but stdin is not a terminal",
+            prompt.trim_end_matches(": ").to_lowercase()
+        ))?;
+    }
+    let secret = rpassword::prompt_password(prompt)?;
+    if confirm && rpassword::prompt_password("Repeat to confirm: ")? != secret {
+        Err("entered values don't match")?;
+    }
+    if secret.is_empty() {
+        Err("empty value is not allowed")?;
+    }
+    Ok(secret)
+}
+
+/// Reads the given number of secrets from stdin, one per line.
+pub fn stdin_secrets(count: usize) -> Result<Vec<String>> {
+    let mut buf = String::new();
+    stdin().read_to_string(&mut buf)?;
+    let secrets: Vec<String> = buf
+        .lines()
+        .map(|it| it.trim_end_matches('\r').to_string())
+        .take(count)
+        .collect();
+    if secrets.len() < count || secrets.iter().any(|it| it.is_empty()) {
+        Err(format!("expected {count} non-empty line(s) on stdin"))?;
+    }
+    Ok(secrets)
+}
@@ -1,103 +1,142 @@
+use crate::output;
 use crate::settings;
 use crate::verbosity;
 use crate::Result;
-use colored_json::ToColoredJson;
-use serde::Deserialize;
-use serde::Serialize;
-use serde_json::{json, Value};
-use std::i64;
-
-#[derive(Serialize, Deserialize)]
-pub struct RpcResponse {
-    pub result: Option<Value>,
-    pub error: Option<Value>,
+pub use btcmap_cli::{Client, DryRun, RpcError, RpcResponse, TransportError};
+use serde_json::Value;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+/// Prints the result of a response, or turns its error into an RpcError.
+pub trait Print {
+    fn print(&self) -> Result<()>;
 }
 
-impl RpcResponse {
-    pub fn print(&self) -> Result<()> {
-        if let Some(result) = &self.result {
-            println!(
-                "{}",
-                serde_json::to_string(&result)?.to_colored_json_auto()?
-            );
-        } else {
-            if let Some(error) = &self.error {
-                println!("{}", serde_json::to_string(&error)?.to_colored_json_auto()?)
-            }
+impl Print for RpcResponse {
+    fn print(&self) -> Result<()> {
+        match &self.result {
+            Some(result) => output::print(result),
+            None => Err(self.to_error())?,
         }
-        Ok(())
     }
 }
 
-pub fn call(method: &str, params: Value) -> Result<RpcResponse> {
-    call_with_auth(method, params, true)
-}
+/// Exit codes returned by the process. Documented in the --help footer, keep
+/// them in sync with EXIT_CODES_HELP.
+pub const EXIT_GENERIC: u8 = 1;
+pub const EXIT_AUTH: u8 = 3;
+pub const EXIT_NOT_FOUND: u8 = 4;
+pub const EXIT_INVALID_PARAMS: u8 = 5;
+pub const EXIT_SERVER: u8 = 6;
+pub const EXIT_TRANSPORT: u8 = 7;
+pub const EXIT_INVOICE_EXPIRED: u8 = 8;
 
-pub fn call_anon(method: &str, params: Value) -> Result<RpcResponse> {
-    call_with_auth(method, params, false)
-}
+pub const EXIT_CODES_HELP: &str = "Exit codes:
+  0  Success
+  1  Generic error (bad local input, settings, I/O)
+  2  Invalid command line usage
+  3  Authentication failure (HTTP 401/403, see below)
+  4  Not found (HTTP 404, JSON-RPC -32601, see below)
+  5  Invalid params (HTTP 400, JSON-RPC -32700, -32600 and -32602)
+  6  Server error (any other HTTP status, JSON-RPC -32603, -32000 to -32099
+     and any other RPC error)
+  7  Transport error (connection, DNS, TLS, timeout or malformed response)
+  8  Invoice expired before it was paid (--wait)
+
+The server reports auth and lookup failures with generic codes, so 3 and 4
+are also chosen from the error message: 3 when it contains \"unauthorized\",
+\"forbidden\", \"credentials\" or \"api key\", otherwise 4 when it contains
+\"not found\". This depends on the server wording, match on the printed
+error code where that matters.
 
-fn call_with_auth(method: &str, params: Value, with_auth: bool) -> Result<RpcResponse> {
-    match verbosity() {
-        i64::MIN..=0 => {}
-        1..=i64::MAX => {
-            println!("Calling method {method} with the following params:");
-            println!(
-                "{}",
-                serde_json::to_string(&params)?.to_colored_json_auto()?
-            );
+RPC errors are printed to stderr as {\"error\": {\"code\", \"message\", \"data\"}}.
+For HTTP errors the code is the status and data is the JSON-RPC error the
+server sent with it, if any.";
+
+/// Codes first, then the message heuristic documented in EXIT_CODES_HELP.
+/// Keep the substrings here and there in sync.
+fn rpc_exit_code(error: &RpcError) -> u8 {
+    let message = error.message.to_lowercase();
+    match error.code {
+        401 | 403 => EXIT_AUTH,
+        _ if message.contains("unauthorized")
+            || message.contains("forbidden")
+            || message.contains("credentials")
+            || message.contains("api key") =>
+        {
+            EXIT_AUTH
         }
+        404 | -32601 => EXIT_NOT_FOUND,
+        _ if message.contains("not found") => EXIT_NOT_FOUND,
+        400 | -32700 | -32600 | -32602 => EXIT_INVALID_PARAMS,
+        _ => EXIT_SERVER,
     }
-    let params = params
-        .as_object()
-        .ok_or("params value is not a valid JSON object")?;
-    let req_body = json!(
-        {"jsonrpc": "2.0", "method": method, "params": params, "id": 1}
-    );
-    let mut api_url = settings::get_str("api_url")?;
-    if api_url.trim().is_empty() {
-        api_url = "https://api.btcmap.org/rpc".into();
-    }
-    if verbosity() >= 2 {
-        println!("Full request body:");
-        println!(
-            "{}",
-            serde_json::to_string(&req_body)?.to_colored_json_auto()?
-        );
+}
+
+/// An invoice waited for with --wait expired unpaid.
+#[derive(Debug)]
+pub struct InvoiceExpired(pub i64);
+
+impl fmt::Display for InvoiceExpired {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invoice {} expired before it was paid", self.0)
     }
-    let mut request = ureq::post(api_url).header("Content-Type", "application/json");
-    if with_auth {
-        request = request.header(
-            "Authorization",
-            format!("Bearer {}", settings::get_str("password")?),
-        );
+}
+
+impl Error for InvoiceExpired {}
+
+/// Maps any error bubbling up to main to a process exit code.
+pub fn exit_code(error: &(dyn Error + 'static)) -> u8 {
+    if let Some(error) = error.downcast_ref::<RpcError>() {
+        rpc_exit_code(error)
+    } else if error.is::<TransportError>() {
+        EXIT_TRANSPORT
+    } else if error.is::<InvoiceExpired>() {
+        EXIT_INVOICE_EXPIRED
+    } else {
+        EXIT_GENERIC
     }
-    let response: RpcResponse = request.send_json(req_body)?.body_mut().read_json()?;
-    match verbosity() {
-        i64::MIN..=0 => {}
-        1..=2 => {
-            if response.result.is_some() {
-                println!("RPC result:");
-                println!(
-                    "{}",
-                    serde_json::to_string(&response.result)?.to_colored_json_auto()?
-                );
-            }
-            if response.error.is_some() {
-                println!("RPC error:");
-                println!(
-                    "{}",
-                    serde_json::to_string(&response.error)?.to_colored_json_auto()?
-                );
-            }
-        }
-        3..=i64::MAX => {
-            println!("RPC response:");
-            println!(
-                "{}",
-                serde_json::to_string(&response)?.to_colored_json_auto()?
-            );
-        }
-    };
-    Ok(response)
+}
+
+pub fn is_dry_run() -> bool {
+    env::var("DRY_RUN").is_ok_and(|it| it == "1")
+}
+
+/// Client for the active profile with the global flags applied.
+pub fn client() -> Result<Client> {
+    let client = anon_client()?;
+    Ok(client.with_token(settings::api_key()?.value))
+}
+
+/// Same as client, without a token. Used to obtain one.
+pub fn anon_client() -> Result<Client> {
+    Ok(Client::new(settings::api_url()?.value)
+        .with_timeout(
+            env_number("HTTP_TIMEOUT").and_then(|it| Duration::try_from_secs_f64(it).ok()),
+        )
+        .with_retries(
+            env_number("HTTP_RETRIES").unwrap_or(0.0) as u32,
+            env_number("HTTP_RETRY_BACKOFF").unwrap_or(1.0),
+        )
+        .with_retry_mutations(env::var("RETRY_MUTATIONS").is_ok_and(|it| it == "1"))
+        .with_dry_run(is_dry_run())
+        .with_verbosity(verbosity()))
+}
+
+pub fn call(method: &str, params: Value) -> Result<RpcResponse> {
+    client()?.call(method, params)
+}
+
+pub fn call_anon(method: &str, params: Value) -> Result<RpcResponse> {
+    anon_client()?.call(method, params)
+}
+
+pub fn call_batch(calls: &[(String, Value)]) -> Result<Vec<RpcResponse>> {
+    client()?.call_batch(calls)
+}
+
+fn env_number(name: &str) -> Option<f64> {
+    env::var(name).ok().and_then(|it| it.parse().ok())
 }
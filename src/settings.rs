@@ -1,42 +1,240 @@
-use crate::Result;
-use dirs::data_dir;
-use rusqlite::{params, Connection};
-use std::{fs::create_dir, path::PathBuf};
+use crate::{credentials, Result};
+use rusqlite::{params, Connection, OpenFlags};
+use std::{env, fs::create_dir_all, path::PathBuf};
 
+pub const DEFAULT_PROFILE: &str = "default";
+pub const DEFAULT_API_URL: &str = "https://api.btcmap.org/rpc";
+
+pub struct Profile {
+    pub name: String,
+    pub api_url: String,
+    pub credential_backend: String,
+}
+
+/// Stores a value in the active profile.
 pub fn put_str(name: &str, value: &str) -> Result<()> {
-    connect()?.execute(
-        &format!("UPDATE settings SET json = json_set(json, '$.{name}', ?1);"),
-        params![value],
+    put_profile_str(&active_profile()?, name, value)
+}
+
+/// Reads a value from the active profile.
+pub fn get_str(name: &str) -> Result<String> {
+    get_profile_str(&active_profile()?, name)
+}
+
+pub fn put_profile_str(profile: &str, name: &str, value: &str) -> Result<()> {
+    let updated = connect()?.execute(
+        &format!("UPDATE profile SET json = json_set(json, '$.{name}', ?1) WHERE name = ?2;"),
+        params![value, profile],
     )?;
+    if updated == 0 {
+        Err(missing_profile(profile))?;
+    }
     Ok(())
 }
 
-pub fn get_str(name: &str) -> Result<String> {
+pub fn get_profile_str(profile: &str, name: &str) -> Result<String> {
     let conn = connect()?;
     let mut stmt = conn.prepare(&format!(
-        "SELECT json_extract(json, '$.{name}') FROM settings;"
+        "SELECT json_extract(json, '$.{name}') FROM profile WHERE name = ?1;"
     ))?;
-    let mut rows = stmt.query(())?;
+    let mut rows = stmt.query(params![profile])?;
     let res = match rows.next()? {
         Some(first_row) => first_row.get(0).unwrap_or("".into()),
-        None => "".into(),
+        None => Err(missing_profile(profile))?,
     };
     Ok(res)
 }
 
-fn connect() -> Result<Connection> {
+/// A setting value together with the place it was taken from: a command line
+/// flag, an environment variable, the active profile or the built-in default.
+pub struct Resolved {
+    pub value: String,
+    pub source: &'static str,
+}
+
+/// Server URL, --server and BTCMAP_API_URL take precedence over the profile.
+pub fn api_url() -> Result<Resolved> {
+    if let Some(res) = override_value("SERVER_OVERRIDE", "BTCMAP_API_URL") {
+        return Ok(Resolved {
+            value: server_url(&res.value).into(),
+            source: res.source,
+        });
+    }
+    let stored = get_str("api_url")?;
+    if stored.trim().is_empty() {
+        return Ok(Resolved {
+            value: DEFAULT_API_URL.into(),
+            source: "default",
+        });
+    }
+    Ok(Resolved {
+        value: stored,
+        source: "profile",
+    })
+}
+
+/// API token, --api-key and BTCMAP_API_KEY take precedence over the profile.
+pub fn api_key() -> Result<Resolved> {
+    if let Some(res) = api_key_override() {
+        return Ok(res);
+    }
+    Ok(Resolved {
+        value: credentials::get_token()?,
+        source: "profile",
+    })
+}
+
+pub fn api_key_override() -> Option<Resolved> {
+    override_value("API_KEY_OVERRIDE", "BTCMAP_API_KEY")
+}
+
+fn override_value(flag_var: &str, env_var: &str) -> Option<Resolved> {
+    for (var, source) in [(flag_var, "flag"), (env_var, "env")] {
+        if let Ok(value) = env::var(var) {
+            if !value.trim().is_empty() {
+                return Some(Resolved { value, source });
+            }
+        }
+    }
+    None
+}
+
+/// Expands the prod and dev shortcuts accepted wherever a server URL is expected.
+pub fn server_url(url: &str) -> &str {
+    match url {
+        "prod" => DEFAULT_API_URL,
+        "dev" => "http://127.0.0.1:8000/rpc",
+        _ => url,
+    }
+}
+
+/// The profile selected by --profile or BTCMAP_PROFILE, falling back to the
+/// one picked with setup profile use.
+pub fn active_profile() -> Result<String> {
+    if let Ok(profile) = env::var("BTCMAP_PROFILE") {
+        if !profile.trim().is_empty() {
+            return Ok(profile);
+        }
+    }
+    stored_profile()
+}
+
+pub fn stored_profile() -> Result<String> {
+    let conn = connect()?;
+    let res: Option<String> = conn.query_row(
+        "SELECT json_extract(json, '$.profile') FROM settings;",
+        (),
+        |row| row.get(0),
+    )?;
+    Ok(res.unwrap_or(DEFAULT_PROFILE.into()))
+}
+
+pub fn use_profile(name: &str) -> Result<()> {
+    let conn = connect()?;
+    if !profile_exists(&conn, name)? {
+        Err(missing_profile(name))?;
+    }
+    conn.execute(
+        "UPDATE settings SET json = json_set(json, '$.profile', ?1);",
+        params![name],
+    )?;
+    Ok(())
+}
+
+pub fn add_profile(name: &str, api_url: &str) -> Result<()> {
+    let conn = connect()?;
+    if profile_exists(&conn, name)? {
+        Err(format!("profile {name} already exists"))?;
+    }
+    conn.execute(
+        "INSERT INTO profile (name, json) VALUES (?1, json_object('api_url', ?2, 'password', ''));",
+        params![name, api_url],
+    )?;
+    Ok(())
+}
+
+pub fn remove_profile(name: &str) -> Result<()> {
+    if stored_profile()? == name {
+        Err(format!(
+            "profile {name} is in use, switch to another one with setup profile use first"
+        ))?;
+    }
+    if active_profile()? == name {
+        Err(format!(
+            "profile {name} is selected with --profile or BTCMAP_PROFILE, select another one to remove it"
+        ))?;
+    }
+    let backend = credentials::backend_by_name(&get_profile_str(name, "credential_backend")?)?;
+    backend.set(name, "")?;
+    connect()?.execute("DELETE FROM profile WHERE name = ?1;", params![name])?;
+    Ok(())
+}
+
+pub fn profiles() -> Result<Vec<Profile>> {
+    let conn = connect()?;
+    let mut stmt = conn.prepare(
+        "SELECT name, json_extract(json, '$.api_url'), json_extract(json, '$.credential_backend') FROM profile ORDER BY name;",
+    )?;
+    let res = stmt
+        .query_map((), |row| {
+            Ok(Profile {
+                name: row.get(0)?,
+                api_url: row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+                credential_backend: row
+                    .get::<_, Option<String>>(2)?
+                    .unwrap_or(credentials::PLAINTEXT.into()),
+            })
+        })?
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    Ok(res)
+}
+
+fn profile_exists(conn: &Connection, name: &str) -> Result<bool> {
+    let count: i64 = conn.query_row(
+        "SELECT COUNT (*) FROM profile WHERE name = ?1;",
+        params![name],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+fn missing_profile(name: &str) -> String {
+    format!("profile {name} does not exist, run btcmap-cli setup profile add {name}")
+}
+
+pub fn connect() -> Result<Connection> {
     let conn = Connection::open(path()?)?;
     init(&conn)?;
     Ok(conn)
 }
 
+/// Opens the database read-only without creating it, for shell completion
+/// which runs on every tab press. None when nothing was saved yet.
+pub fn connect_existing() -> Result<Option<Connection>> {
+    let path = dirs::data_dir()
+        .ok_or("failed to locate system app data directory")?
+        .join("btcmap-cli")
+        .join("btcmap-cli.db");
+    if !path.exists() {
+        return Ok(None);
+    }
+    Ok(Some(Connection::open_with_flags(
+        path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY,
+    )?))
+}
+
 fn path() -> Result<PathBuf> {
-    let data_dir = data_dir().ok_or("failed to locate system app data directory")?;
+    Ok(data_dir()?.join("btcmap-cli.db"))
+}
+
+pub fn data_dir() -> Result<PathBuf> {
+    let data_dir = dirs::data_dir().ok_or("failed to locate system app data directory")?;
     let data_dir = data_dir.join("btcmap-cli");
     if !data_dir.exists() {
-        create_dir(&data_dir)?;
+        create_dir_all(&data_dir)?;
     }
-    Ok(data_dir.join("btcmap-cli.db"))
+    Ok(data_dir)
 }
 
 fn init(conn: &Connection) -> Result<()> {
@@ -54,5 +252,22 @@ fn init(conn: &Connection) -> Result<()> {
     if rows > 1 {
         Err("settings database has been corrupted")?;
     }
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS profile (name TEXT PRIMARY KEY, json TEXT NOT NULL);",
+        (),
+    )?;
+    let profiles: i64 = conn.query_row("SELECT COUNT (*) FROM profile;", (), |row| row.get(0))?;
+    if profiles == 0 {
+        // Databases created before profiles existed keep the server and token
+        // in the settings row, move them into the default profile
+        conn.execute(
+            "INSERT INTO profile (name, json) SELECT ?1, json_object('api_url', coalesce(json_extract(json, '$.api_url'), ''), 'password', coalesce(json_extract(json, '$.password'), '')) FROM settings;",
+            params![DEFAULT_PROFILE],
+        )?;
+        conn.execute(
+            "UPDATE settings SET json = json_set(json_remove(json, '$.api_url', '$.password'), '$.profile', ?1);",
+            params![DEFAULT_PROFILE],
+        )?;
+    }
     Ok(())
 }
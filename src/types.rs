@@ -0,0 +1,127 @@
+//! Params and results of the methods wrapped by [`Client`](crate::Client).
+//! Results keep fields this crate doesn't know about in `extra`. Known fields
+//! come first when serialized and optional ones are left out when null, so
+//! code that must print exactly what the server sent should use
+//! [`Client::call`](crate::Client::call) instead.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+#[derive(Serialize)]
+pub struct GetElementParams {
+    pub id: i64,
+}
+
+#[derive(Serialize)]
+pub struct SetElementTagParams {
+    pub element_id: i64,
+    pub tag_name: String,
+    pub tag_value: Value,
+}
+
+#[derive(Serialize)]
+pub struct RemoveElementTagParams {
+    pub element_id: i64,
+    pub tag_name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Element {
+    pub id: i64,
+    #[serde(default)]
+    pub tags: Map<String, Value>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Serialize)]
+pub struct GetAreaParams {
+    /// Numeric id or url_alias
+    pub id: String,
+}
+
+#[derive(Serialize)]
+pub struct SetAreaTagParams {
+    pub id: String,
+    pub name: String,
+    pub value: Value,
+}
+
+#[derive(Serialize)]
+pub struct RemoveAreaTagParams {
+    pub id: String,
+    pub tag: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Area {
+    pub id: i64,
+    #[serde(default)]
+    pub tags: Map<String, Value>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl Area {
+    pub fn alias(&self) -> Option<&str> {
+        self.tags.get("url_alias").and_then(Value::as_str)
+    }
+}
+
+#[derive(Serialize)]
+pub struct GetEventsParams {
+    pub include_past: bool,
+    pub include_deleted: bool,
+}
+
+#[derive(Serialize)]
+pub struct GetEventParams {
+    pub id: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Event {
+    pub id: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lat: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lon: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Serialize)]
+pub struct SearchParams {
+    pub query: String,
+    /// Limits results to one kind, e.g. area, element or event
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchResult {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Serialize)]
+pub struct GetWalletsParams {
+    pub include_deleted: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Wallet {
+    pub id: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub xpub: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
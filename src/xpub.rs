@@ -0,0 +1,171 @@
+use crate::Result;
+use bitcoin::{
+    base58,
+    bip32::{ChildNumber, Fingerprint, Xpub},
+    secp256k1::Secp256k1,
+    Address, Network,
+};
+
+/// Address type implied by the SLIP-132 version bytes of a key.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ScriptKind {
+    /// Legacy, BIP44
+    P2pkh,
+    /// Nested segwit, BIP49
+    P2shP2wpkh,
+    /// Native segwit, BIP84
+    P2wpkh,
+}
+
+impl ScriptKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScriptKind::P2pkh => "p2pkh",
+            ScriptKind::P2shP2wpkh => "p2sh-p2wpkh",
+            ScriptKind::P2wpkh => "p2wpkh",
+        }
+    }
+}
+
+const XPUB: [u8; 4] = [0x04, 0x88, 0xB2, 0x1E];
+const TPUB: [u8; 4] = [0x04, 0x35, 0x87, 0xCF];
+
+/// Version bytes of public keys: prefix, bytes, network, script kind.
+const PUBLIC_VERSIONS: [(&str, [u8; 4], Network, ScriptKind); 6] = [
+    ("xpub", XPUB, Network::Bitcoin, ScriptKind::P2pkh),
+    (
+        "ypub",
+        [0x04, 0x9D, 0x7C, 0xB2],
+        Network::Bitcoin,
+        ScriptKind::P2shP2wpkh,
+    ),
+    (
+        "zpub",
+        [0x04, 0xB2, 0x47, 0x46],
+        Network::Bitcoin,
+        ScriptKind::P2wpkh,
+    ),
+    ("tpub", TPUB, Network::Testnet, ScriptKind::P2pkh),
+    (
+        "upub",
+        [0x04, 0x4A, 0x52, 0x62],
+        Network::Testnet,
+        ScriptKind::P2shP2wpkh,
+    ),
+    (
+        "vpub",
+        [0x04, 0x5F, 0x1C, 0x4F],
+        Network::Testnet,
+        ScriptKind::P2wpkh,
+    ),
+];
+
+/// Version bytes of private keys, recognized only to refuse them.
+const PRIVATE_VERSIONS: [(&str, [u8; 4]); 6] = [
+    ("xprv", [0x04, 0x88, 0xAD, 0xE4]),
+    ("yprv", [0x04, 0x9D, 0x78, 0x78]),
+    ("zprv", [0x04, 0xB2, 0x43, 0x0C]),
+    ("tprv", [0x04, 0x35, 0x83, 0x94]),
+    ("uprv", [0x04, 0x4A, 0x4E, 0x28]),
+    ("vprv", [0x04, 0x5F, 0x18, 0xBC]),
+];
+
+/// A validated extended public key. Keys with SLIP-132 prefixes are
+/// normalized to xpub/tpub, the prefix only decides the address type.
+pub struct ExtendedKey {
+    pub prefix: &'static str,
+    pub network: Network,
+    pub kind: ScriptKind,
+    pub xpub: Xpub,
+}
+
+impl ExtendedKey {
+    /// Problems that don't make the key unusable but are likely mistakes.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut res = vec![];
+        if self.xpub.depth != 3 {
+            res.push(format!(
+                "key is at depth {}, wallets usually export account keys at depth 3 (m/purpose'/coin'/account')",
+                self.xpub.depth
+            ));
+        }
+        if self.xpub.depth != 0 && self.xpub.parent_fingerprint == Fingerprint::default() {
+            res.push("key has no parent fingerprint".into());
+        }
+        res
+    }
+
+    /// Address at chain/index below the key, chain 0 is receive and 1 is change.
+    pub fn address(&self, chain: u32, index: u32) -> Result<Address> {
+        let path = [
+            ChildNumber::from_normal_idx(chain)?,
+            ChildNumber::from_normal_idx(index)?,
+        ];
+        let key = self
+            .xpub
+            .derive_pub(&Secp256k1::verification_only(), &path)?;
+        let key = key.to_pub();
+        Ok(match self.kind {
+            ScriptKind::P2pkh => Address::p2pkh(key, self.network),
+            ScriptKind::P2shP2wpkh => Address::p2shwpkh(&key, self.network),
+            ScriptKind::P2wpkh => Address::p2wpkh(&key, self.network),
+        })
+    }
+}
+
+/// Checks base58check encoding, version bytes, length and depth and decodes
+/// the key.
+pub fn parse(key: &str) -> Result<ExtendedKey> {
+    let mut data = base58::decode_check(key.trim())
+        .map_err(|e| format!("not a valid base58check string: {e}"))?;
+    if data.len() != 78 {
+        Err(format!(
+            "extended keys are 78 bytes long, this one is {}",
+            data.len()
+        ))?;
+    }
+    let version: [u8; 4] = data[..4].try_into()?;
+    if let Some((prefix, _)) = PRIVATE_VERSIONS.iter().find(|it| it.1 == version) {
+        Err(format!(
+            "this is a private key ({prefix}), never share it. Export the matching public key instead"
+        ))?;
+    }
+    let (prefix, _, network, kind) =
+        *PUBLIC_VERSIONS
+            .iter()
+            .find(|it| it.1 == version)
+            .ok_or(format!(
+            "unknown version bytes {:02x?}, expected one of xpub, ypub, zpub, tpub, upub or vpub",
+            version
+        ))?;
+    let depth = data[4];
+    let parent_fingerprint = &data[5..9];
+    let child_number = &data[9..13];
+    if depth == 0 && (parent_fingerprint != [0; 4] || child_number != [0; 4]) {
+        Err("key is at depth 0 but has a parent fingerprint or child number")?;
+    }
+    let normalized = match network {
+        Network::Bitcoin => XPUB,
+        _ => TPUB,
+    };
+    data[..4].copy_from_slice(&normalized);
+    let xpub = Xpub::decode(&data).map_err(|e| format!("invalid extended key: {e}"))?;
+    Ok(ExtendedKey {
+        prefix,
+        network,
+        kind,
+        xpub,
+    })
+}
+
+/// Encodes a key with the SLIP-132 prefix matching its network and address
+/// type, the form servers that only know about xpubs expect.
+pub fn encode(xpub: &Xpub, network: Network, kind: ScriptKind) -> Result<String> {
+    let (_, version, _, _) = PUBLIC_VERSIONS
+        .iter()
+        .find(|it| (it.2 == Network::Bitcoin) == (network == Network::Bitcoin) && it.3 == kind)
+        .ok_or("no version bytes for this network and address type")?;
+    let mut data = xpub.encode();
+    data[..4].copy_from_slice(version);
+    Ok(base58::encode_check(&data))
+}